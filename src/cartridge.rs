@@ -0,0 +1,514 @@
+use std::fmt;
+
+use crate::constants::*;
+use crate::mappers::Cartridge;
+
+/// Why a ROM could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// The file is shorter than its header or than the ROM size it declares.
+    Truncated { len: usize, expected: usize },
+    UnknownCartridgeType(u8),
+    UnsupportedMapper(Cartridge),
+    UnsupportedRomSize { cartridge: Cartridge, kib: usize },
+    BadRomSizeCode(u8),
+    BadRamSizeCode(u8),
+    /// The header checksum at 0x14D does not match, which the boot ROM refuses
+    /// to run. `load_rom` only returns it as a warning and loads the ROM anyway.
+    ChecksumMismatch { expected: u8, actual: u8 },
+    /// The battery save exists but could not be read. `load_rom` returns it as
+    /// a warning and starts the game without it.
+    UnreadableSave(std::io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::Truncated { len, expected } => write!(f, "ROM is truncated: {len} bytes, expected at least {expected}"),
+            LoadError::UnknownCartridgeType(value) => write!(f, "unknown cartridge type ${value:02X}"),
+            LoadError::UnsupportedMapper(cartridge) => write!(f, "cartridge type {cartridge} is not supported"),
+            LoadError::UnsupportedRomSize { cartridge, kib } => write!(f, "{cartridge} cartridges with {kib} KiB of ROM are not supported"),
+            LoadError::BadRomSizeCode(code) => write!(f, "invalid ROM size code ${code:02X}"),
+            LoadError::BadRamSizeCode(code) => write!(f, "invalid RAM size code ${code:02X}"),
+            LoadError::ChecksumMismatch { expected, actual } => write!(f, "header checksum mismatch: header says ${expected:02X}, computed ${actual:02X}"),
+            LoadError::UnreadableSave(e) => write!(f, "failed to read the battery save: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// The logo bitmap at 0x104, which the boot ROM compares before starting the game.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Old licensee code meaning the new licensee code at 0x144 applies.
+const USE_NEW_LICENSEE: u8 = 0x33;
+/// MMM01 compilations keep their menu, and their own header, in the last 32 KiB.
+const MMM01_MENU_SIZE: usize = 0x8000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbSupport {
+    None,
+    Compatible,
+    Only,
+}
+
+/// The cartridge header at 0x100..0x150, decoded without judging whether the
+/// emulator can run the game, so that broken ROMs can still be inspected.
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub old_licensee: u8,
+    pub new_licensee: String,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub logo_valid: bool,
+    pub computed_header_checksum: u8,
+    pub computed_global_checksum: u16,
+}
+
+fn header_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches(char::from(0)).to_string()
+}
+
+impl CartridgeHeader {
+    pub fn parse(game_bytes: &[u8]) -> Result<CartridgeHeader, LoadError> {
+        if game_bytes.len() < HEADER_END {
+            return Err(LoadError::Truncated { len: game_bytes.len(), expected: HEADER_END });
+        }
+        let cgb_flag = game_bytes[CGB_FLAG_ADDR];
+        let cgb_support = match cgb_flag {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        // Later cartridges shortened the title to make room for the CGB flag
+        // and then for a four letter manufacturer code.
+        let manufacturer_bytes = &game_bytes[MANUFACTURER_CODE_ADDR];
+        let has_manufacturer_code = cgb_support != CgbSupport::None
+            && manufacturer_bytes.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+        let title_end = if has_manufacturer_code {
+            MANUFACTURER_CODE_ADDR.start
+        } else if cgb_support != CgbSupport::None {
+            CGB_FLAG_ADDR
+        } else {
+            CGB_FLAG_ADDR + 1
+        };
+
+        let computed_header_checksum = game_bytes[TITLE_ADDR.start..HEADER_CHECKSUM_ADDR].iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+        let computed_global_checksum = game_bytes.iter().enumerate()
+            .filter(|(i, _)| *i != GLOBAL_CHECKSUM_ADDR && *i != GLOBAL_CHECKSUM_ADDR + 1)
+            .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16));
+
+        Ok(CartridgeHeader {
+            title: header_text(&game_bytes[TITLE_ADDR.start..title_end]),
+            manufacturer_code: has_manufacturer_code.then(|| header_text(manufacturer_bytes)),
+            cgb_support,
+            sgb_support: game_bytes[SGB_FLAG_ADDR] == 0x03,
+            old_licensee: game_bytes[OLD_LICENSEE_ADDR],
+            new_licensee: header_text(&game_bytes[NEW_LICENSEE_ADDR]),
+            cartridge_type: game_bytes[CARTRIDGE_TYPE_ADDR],
+            rom_size_code: game_bytes[ROM_SIZE_ADDR],
+            ram_size_code: game_bytes[RAM_SIZE_ADDR],
+            japanese: game_bytes[DESTINATION_ADDR] == 0x00,
+            version: game_bytes[VERSION_ADDR],
+            header_checksum: game_bytes[HEADER_CHECKSUM_ADDR],
+            global_checksum: u16::from_be_bytes([game_bytes[GLOBAL_CHECKSUM_ADDR], game_bytes[GLOBAL_CHECKSUM_ADDR + 1]]),
+            logo_valid: game_bytes[NINTENDO_LOGO_ADDR] == NINTENDO_LOGO,
+            computed_header_checksum,
+            computed_global_checksum,
+        })
+    }
+
+    /// Offset of the header that identifies the cartridge. It is the one at the
+    /// start of the ROM, except on MMM01 compilations, which start with the first
+    /// game's header and carry the MMM01 one with the menu at the end.
+    pub fn locate(game_bytes: &[u8]) -> usize {
+        if game_bytes.len() < 2 * MMM01_MENU_SIZE {
+            return 0;
+        }
+        let menu_start = game_bytes.len() - MMM01_MENU_SIZE;
+        match CartridgeHeader::parse(&game_bytes[menu_start..]) {
+            Ok(menu) if (0x0B..=0x0D).contains(&menu.cartridge_type) && menu.header_checksum_valid() => menu_start,
+            _ => 0,
+        }
+    }
+
+    /// Parses the header found by `locate`, with the global checksum computed
+    /// over the whole ROM.
+    pub fn parse_rom(game_bytes: &[u8]) -> Result<CartridgeHeader, LoadError> {
+        let offset = CartridgeHeader::locate(game_bytes);
+        let mut header = CartridgeHeader::parse(&game_bytes[offset..])?;
+        let checksum_addr = offset + GLOBAL_CHECKSUM_ADDR;
+        header.computed_global_checksum = game_bytes.iter().enumerate()
+            .filter(|(i, _)| *i != checksum_addr && *i != checksum_addr + 1)
+            .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16));
+        Ok(header)
+    }
+
+    pub fn cartridge(&self) -> Result<Cartridge, LoadError> {
+        Cartridge::try_from(self.cartridge_type)
+    }
+
+    /// Number of 16 KiB ROM banks.
+    pub fn rom_banks(&self) -> Result<usize, LoadError> {
+        match self.rom_size_code {
+            0x00..=0x08 => Ok(2 << self.rom_size_code),
+            _ => Err(LoadError::BadRomSizeCode(self.rom_size_code)),
+        }
+    }
+
+    /// Number of 8 KiB RAM banks.
+    pub fn ram_banks(&self) -> Result<usize, LoadError> {
+        match self.ram_size_code {
+            0x00 => Ok(0),
+            // A single 2 KiB chip, rounded up to a whole bank.
+            0x01 => Ok(1),
+            0x02 => Ok(1),
+            0x03 => Ok(4),
+            0x04 => Ok(16),
+            0x05 => Ok(8),
+            _ => Err(LoadError::BadRamSizeCode(self.ram_size_code)),
+        }
+    }
+
+    /// Whether the boot ROM would accept the header checksum.
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Whether the global checksum matches. Real hardware never checks it.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    /// Fails with `ChecksumMismatch` where the boot ROM would lock up.
+    pub fn verify_header_checksum(&self) -> Result<(), LoadError> {
+        if !self.header_checksum_valid() {
+            return Err(LoadError::ChecksumMismatch { expected: self.header_checksum, actual: self.computed_header_checksum });
+        }
+        Ok(())
+    }
+
+    /// Whether the publisher is given by the new licensee code at 0x144.
+    pub fn uses_new_licensee(&self) -> bool {
+        self.old_licensee == USE_NEW_LICENSEE
+    }
+
+    /// Whether the SGB flag takes effect, which needs the new licensee code.
+    pub fn uses_sgb_functions(&self) -> bool {
+        self.sgb_support && self.uses_new_licensee()
+    }
+
+    /// Publisher name, looked up through whichever licensee code applies.
+    pub fn licensee(&self) -> Option<&'static str> {
+        if self.uses_new_licensee() {
+            new_licensee_name(&self.new_licensee)
+        } else {
+            old_licensee_name(self.old_licensee)
+        }
+    }
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "b-ai",
+        "20" => "kss",
+        "22" => "pow",
+        "24" => "PCM Complete",
+        "25" => "san-x",
+        "28" => "Kemco Japan",
+        "29" => "seta",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean/Acclaim",
+        "34" => "Konami",
+        "35" => "Hector",
+        "37" => "Taito",
+        "38" => "Hudson",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu",
+        "46" => "angel",
+        "47" => "Bullet-Proof",
+        "49" => "irem",
+        "50" => "Absolute",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "53" => "American sammy",
+        "54" => "Konami",
+        "55" => "Hi tech entertainment",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus",
+        "61" => "Virgin",
+        "64" => "LucasArts",
+        "67" => "Ocean",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "sculptured",
+        "75" => "sci",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "misawa",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten Intermedia",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video system",
+        "93" => "Ocean/Acclaim",
+        "95" => "Varie",
+        "96" => "Yonezawa/s'pal",
+        "97" => "Kaneko",
+        "99" => "Pack in soft",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        _ => return None,
+    })
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Games",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "Sammy USA",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F | 0xC2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai",
+        0x8E => "Ape",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Squaresoft",
+        0xC5 => "Data East",
+        0xC6 => "Tonkinhouse",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra",
+        0xCB => "Vap",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "Sofel",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDD => "NCS",
+        0xDE => "Human",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a header with the given title, type and ROM size code at
+    /// `offset`, with a valid logo and header checksum.
+    fn write_header(rom: &mut [u8], offset: usize, title: &str, cartridge_type: u8, rom_size_code: u8) {
+        let header = &mut rom[offset..offset + HEADER_END];
+        header[NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        header[TITLE_ADDR.start..TITLE_ADDR.start + title.len()].copy_from_slice(title.as_bytes());
+        header[CARTRIDGE_TYPE_ADDR] = cartridge_type;
+        header[ROM_SIZE_ADDR] = rom_size_code;
+        header[HEADER_CHECKSUM_ADDR] = header[TITLE_ADDR.start..HEADER_CHECKSUM_ADDR].iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+    }
+
+    /// A 128 KiB MMM01 image: the first game's header at 0 and the menu's at
+    /// the start of the last 32 KiB.
+    fn mmm01_image() -> Vec<u8> {
+        let mut rom = vec![0; 0x20000];
+        write_header(&mut rom, 0, "FIRST GAME", 0x01, 0x00);
+        write_header(&mut rom, 0x18000, "MENU", 0x0B, 0x02);
+        rom
+    }
+
+    #[test]
+    fn parse_decodes_header_fields() {
+        let mut rom = vec![0; 0x8000];
+        write_header(&mut rom, 0, "TETRIS", 0x03, 0x01);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.cartridge_type, 0x03);
+        assert_eq!(header.rom_banks().unwrap(), 4);
+        assert_eq!(header.ram_banks().unwrap(), 0);
+        assert!(header.logo_valid);
+        assert!(header.header_checksum_valid());
+        rom[TITLE_ADDR.start] = b'X';
+        assert!(CartridgeHeader::parse(&rom).unwrap().verify_header_checksum().is_err());
+    }
+
+    #[test]
+    fn ram_size_codes_give_whole_banks() {
+        let mut header = CartridgeHeader::parse(&[0; HEADER_END]).unwrap();
+        for (code, banks) in [(0x00, 0), (0x01, 1), (0x02, 1), (0x03, 4), (0x04, 16), (0x05, 8)] {
+            header.ram_size_code = code;
+            assert_eq!(header.ram_banks().unwrap(), banks);
+        }
+        header.ram_size_code = 0x06;
+        assert!(matches!(header.ram_banks(), Err(LoadError::BadRamSizeCode(0x06))));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_rom() {
+        assert!(matches!(CartridgeHeader::parse(&[0; 0x100]), Err(LoadError::Truncated { len: 0x100, .. })));
+    }
+
+    #[test]
+    fn locate_finds_the_mmm01_menu_header() {
+        let rom = mmm01_image();
+        assert_eq!(CartridgeHeader::locate(&rom), 0x18000);
+        let header = CartridgeHeader::parse_rom(&rom).unwrap();
+        assert_eq!(header.title, "MENU");
+        assert_eq!(header.cartridge_type, 0x0B);
+        assert_eq!(header.rom_banks().unwrap(), 8);
+        // The global checksum covers the whole ROM, not just the menu.
+        let sum = rom.iter().fold(0u16, |checksum, byte| checksum.wrapping_add(*byte as u16));
+        assert_eq!(header.computed_global_checksum, sum);
+    }
+
+    #[test]
+    fn locate_ignores_headers_that_are_not_mmm01() {
+        let mut rom = mmm01_image();
+        rom[0x18000 + HEADER_CHECKSUM_ADDR] ^= 0xFF;
+        assert_eq!(CartridgeHeader::locate(&rom), 0);
+        assert_eq!(CartridgeHeader::parse_rom(&rom).unwrap().title, "FIRST GAME");
+
+        let mut rom = mmm01_image();
+        write_header(&mut rom, 0x18000, "MENU", 0x01, 0x02);
+        assert_eq!(CartridgeHeader::locate(&rom), 0);
+
+        let mut rom = vec![0; 0x8000];
+        write_header(&mut rom, 0, "MENU", 0x0B, 0x00);
+        assert_eq!(CartridgeHeader::locate(&rom), 0);
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use rusting_empty::mappers::MapperKind;
+use rusting_empty::{Model, Speed};
+
+pub const USAGE: &str = "\
+Usage:
+    rusting_empty [run] <ROM> [options]    Play a game in an SDL window
+    rusting_empty info <ROM>               Print the cartridge header
+    rusting_empty test <ROM> [options]     Run headless and report the serial output
+    rusting_empty help                     Show this message
+
+Options:
+    --scale <N>         Window scale factor (default 3)
+    --mute              Disable audio output
+    --save-dir <DIR>    Directory for battery saves (default ./saved_games)
+    --model <MODEL>     Hardware model: dmg, mgb or sgb (default dmg)
+    --speed <SPEED>     Emulation speed, 0.25 to 8 or unlimited (default 1)
+    --ff-speed <SPEED>  Speed while the fast-forward key is held (default unlimited)
+    --rewind-mib <N>    Memory kept for rewinding, 0 disables it (default 32)
+    --mapper <NAME>     Force the cartridge mapper, e.g. mbc5, wisdom-tree, sachen-mmc1 or m161
+    --camera <PATH>     PGM or PNG image, or a directory of them, for the Game Boy Camera
+    --frames <N>        Frames to run before `test` gives up (default 3600)";
+
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub struct RunOptions {
+    pub rom: PathBuf,
+    pub scale: f32,
+    pub mute: bool,
+    pub save_dir: Option<PathBuf>,
+    pub model: Model,
+    pub rewind_budget: usize,
+    pub speed: Speed,
+    pub fast_forward: Speed,
+    pub camera: Option<PathBuf>,
+    pub mapper: Option<MapperKind>,
+}
+
+pub struct TestOptions {
+    pub rom: PathBuf,
+    pub frames: u64,
+    pub model: Model,
+    pub mapper: Option<MapperKind>,
+}
+
+pub enum Command {
+    Run(RunOptions),
+    Info { rom: PathBuf },
+    Test(TestOptions),
+    Help,
+}
+
+fn option_value(option: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("missing value for '{option}'"))
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{value}' for '{option}'"))
+}
+
+/// Parses the arguments following the program name.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.peekable();
+    let subcommand = match args.peek().map(|arg| arg.as_str()) {
+        None => return Err("no ROM given".to_string()),
+        Some("help" | "-h" | "--help") => return Ok(Command::Help),
+        Some(name @ ("run" | "info" | "test")) => {
+            let name = name.to_string();
+            args.next();
+            name
+        },
+        Some(_) => "run".to_string(),
+    };
+
+    let mut rom = None;
+    let mut scale: f32 = 3.0;
+    let mut mute = false;
+    let mut save_dir = None;
+    let mut model = Model::Dmg;
+    let mut frames: u64 = 3600;
+    let mut rewind_mib: usize = 32;
+    let mut speed = Speed::Multiplier(1.0);
+    let mut fast_forward = Speed::Unlimited;
+    let mut camera = None;
+    let mut mapper = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" if subcommand == "run" => {
+                scale = parse_number(&arg, &option_value(&arg, &mut args)?)?;
+                if !scale.is_finite() || scale <= 0.0 {
+                    return Err(format!("window scale must be positive, got {scale}"));
+                }
+            },
+            "--mute" if subcommand == "run" => mute = true,
+            "--save-dir" if subcommand == "run" => save_dir = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+            "--speed" if subcommand == "run" => speed = option_value(&arg, &mut args)?.parse()?,
+            "--ff-speed" if subcommand == "run" => fast_forward = option_value(&arg, &mut args)?.parse()?,
+            "--camera" if subcommand == "run" => camera = Some(PathBuf::from(option_value(&arg, &mut args)?)),
+            "--rewind-mib" if subcommand == "run" => rewind_mib = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+            "--model" if subcommand != "info" => model = option_value(&arg, &mut args)?.parse()?,
+            "--mapper" if subcommand != "info" => mapper = Some(option_value(&arg, &mut args)?.parse()?),
+            "--frames" if subcommand == "test" => frames = parse_number(&arg, &option_value(&arg, &mut args)?)?,
+            option if option.starts_with("--") => return Err(format!("unexpected option '{option}' for '{subcommand}'")),
+            path => {
+                if rom.is_some() {
+                    return Err(format!("unexpected argument '{path}'"));
+                }
+                rom = Some(PathBuf::from(path));
+            },
+        }
+    }
+    let rom = rom.ok_or_else(|| "no ROM given".to_string())?;
+
+    Ok(match subcommand.as_str() {
+        "info" => Command::Info { rom },
+        "test" => Command::Test(TestOptions { rom, frames, model, mapper }),
+        _ => Command::Run(RunOptions { rom, scale, mute, save_dir, model, rewind_budget: rewind_mib << 20, speed, fast_forward, camera, mapper }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_err(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{args:?} should not parse"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn run_is_the_default_subcommand() {
+        let Ok(Command::Run(options)) = parse(&["game.gb", "--scale", "2", "--mute", "--speed", "unlimited", "--mapper", "mbc5"]) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.scale, 2.0);
+        assert!(options.mute);
+        assert!(matches!(options.speed, Speed::Unlimited));
+        assert_eq!(options.mapper, Some(MapperKind::MBC5));
+        assert_eq!(options.rewind_budget, 32 << 20);
+    }
+
+    #[test]
+    fn test_takes_frames() {
+        let Ok(Command::Test(options)) = parse(&["test", "cpu_instrs.gb", "--frames", "10"]) else {
+            panic!("expected a test command");
+        };
+        assert_eq!(options.rom, PathBuf::from("cpu_instrs.gb"));
+        assert_eq!(options.frames, 10);
+        assert!(matches!(parse(&["--help"]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn reports_missing_and_extra_arguments() {
+        assert_eq!(parse_err(&[]), "no ROM given");
+        assert_eq!(parse_err(&["info"]), "no ROM given");
+        assert_eq!(parse_err(&["a.gb", "b.gb"]), "unexpected argument 'b.gb'");
+        assert_eq!(parse_err(&["a.gb", "--scale"]), "missing value for '--scale'");
+    }
+
+    #[test]
+    fn reports_options_of_other_subcommands() {
+        assert_eq!(parse_err(&["a.gb", "--frames", "10"]), "unexpected option '--frames' for 'run'");
+        assert_eq!(parse_err(&["test", "a.gb", "--mute"]), "unexpected option '--mute' for 'test'");
+        assert_eq!(parse_err(&["info", "a.gb", "--model", "dmg"]), "unexpected option '--model' for 'info'");
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        assert_eq!(parse_err(&["a.gb", "--scale", "big"]), "invalid value 'big' for '--scale'");
+        assert_eq!(parse_err(&["a.gb", "--scale", "0"]), "window scale must be positive, got 0");
+        assert_eq!(parse_err(&["test", "a.gb", "--frames", "-1"]), "invalid value '-1' for '--frames'");
+        assert!(parse(&["a.gb", "--speed", "fast"]).is_err());
+        assert!(parse(&["a.gb", "--model", "cgb"]).is_err());
+        assert!(parse(&["a.gb", "--mapper", "mbc9"]).is_err());
+    }
+
+    #[test]
+    fn save_dir_is_only_parsed() {
+        let dir = std::env::temp_dir().join(format!("rusting_empty_cli_{}", std::process::id()));
+        let Ok(Command::Run(options)) = parse(&["a.gb", "--save-dir", dir.to_str().unwrap()]) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.save_dir, Some(dir.clone()));
+        assert!(!dir.exists());
+    }
+}
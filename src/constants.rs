@@ -1,4 +1,4 @@
-use std::ops::RangeInclusive;
+use std::ops::{Range, RangeInclusive};
 
 pub const GB_ROM_BANK_SIZE: usize = 16 * 1024;
 pub const GB_INTERNAL_RAM_SIZE: usize = 8 * 1024;
@@ -6,10 +6,34 @@ pub const GB_VRAM_SIZE: usize = 8 * 1024;
 pub const OAM_SIZE: usize = 160;
 pub const CARTRIDGE_RAM_SIZE: usize = 8 * 1024;
 
+pub const NINTENDO_LOGO_ADDR: Range<usize> = 0x104..0x134;
+pub const TITLE_ADDR: Range<usize> = 0x134..0x143;
+pub const MANUFACTURER_CODE_ADDR: Range<usize> = 0x13F..0x143;
+pub const CGB_FLAG_ADDR: usize = 0x143;
+pub const NEW_LICENSEE_ADDR: Range<usize> = 0x144..0x146;
+pub const SGB_FLAG_ADDR: usize = 0x146;
+pub const CARTRIDGE_TYPE_ADDR: usize = 0x147;
+pub const ROM_SIZE_ADDR: usize = 0x148;
+pub const RAM_SIZE_ADDR: usize = 0x149;
+pub const DESTINATION_ADDR: usize = 0x14A;
+pub const OLD_LICENSEE_ADDR: usize = 0x14B;
+pub const VERSION_ADDR: usize = 0x14C;
+pub const HEADER_CHECKSUM_ADDR: usize = 0x14D;
+pub const GLOBAL_CHECKSUM_ADDR: usize = 0x14E;
+pub const HEADER_END: usize = 0x150;
+
 pub const SCREEN_HEIGHT: usize = 144;
 pub const SCREEN_WIDTH: usize = 160;
+pub const CAMERA_HEIGHT: usize = 112;
+pub const CAMERA_WIDTH: usize = 128;
 pub const NUM_DOTS_PER_LINE: u16 = 456;
 pub const NUM_SCAN_LINES: u8 = 154;
+pub const CYCLES_PER_FRAME: u64 = NUM_DOTS_PER_LINE as u64 * NUM_SCAN_LINES as u64;
+pub const FRAMES_PER_SECOND: f64 = 59.7;
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 8.0;
+pub const SPEED_PRESETS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+pub const REWIND_INTERVAL_FRAMES: u32 = 4;
 
 pub const LCDC_BG_WIN_DISPLAY_BIT: u8 = 0;
 pub const LCDC_OBJ_ENABLE_BIT: u8 = 1;
@@ -41,6 +65,7 @@ pub const IF_ADDR: u16 = 0xFF0F;
 pub const IE_ADDR: u16 = 0xFFFF;
 
 pub const JOYP_ADDR: u16 = 0xFF00;
+pub const JOYPAD_POLL_TICKS: u64 = 7022;
 
 pub const DIV_ADDR: u16 = 0xFF04;
 pub const TIMA_ADDR: u16 = 0xFF05;
@@ -5,6 +5,8 @@ use crate::registers::RegisterBank;
 use crate::memory::AddressSpace;
 use crate::opcodes::{get_instr, Opcode};
 use crate::constants::*;
+use crate::gameboy::Model;
+use crate::savestate::{StateError, StateReader, StateWriter};
 
 pub const DEBUG: bool = false;
 // pub const DEBUG: bool = true;
@@ -109,11 +111,54 @@ impl CPU {
         self.clock += nticks as u64;
     }
 
-    pub fn boot(&mut self, memory: &mut AddressSpace) {
-        self.registers.set_AF(0x01B0);
-        self.registers.set_BC(0x0013);
-        self.registers.set_DE(0x00D8);
-        self.registers.set_HL(0x014D);
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.registers.AF());
+        state.write_u16(self.registers.BC());
+        state.write_u16(self.registers.DE());
+        state.write_u16(self.registers.HL());
+        state.write_u16(self.registers.SP);
+        state.write_u16(self.registers.PC());
+        state.write_bool(self.master_interrupt_enable);
+        state.write_bool(self.enable_interrupts_next_instr);
+        state.write_u64(self.clock);
+        state.write_bool(self.halted);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.registers.set_AF(state.read_u16()?);
+        self.registers.set_BC(state.read_u16()?);
+        self.registers.set_DE(state.read_u16()?);
+        self.registers.set_HL(state.read_u16()?);
+        self.registers.SP = state.read_u16()?;
+        self.registers.write_PC(state.read_u16()?);
+        self.master_interrupt_enable = state.read_bool()?;
+        self.enable_interrupts_next_instr = state.read_bool()?;
+        self.clock = state.read_u64()?;
+        self.halted = state.read_bool()?;
+        Ok(())
+    }
+
+    pub fn boot(&mut self, memory: &mut AddressSpace, model: Model) {
+        match model {
+            Model::Dmg => {
+                self.registers.set_AF(0x01B0);
+                self.registers.set_BC(0x0013);
+                self.registers.set_DE(0x00D8);
+                self.registers.set_HL(0x014D);
+            },
+            Model::Mgb => {
+                self.registers.set_AF(0xFFB0);
+                self.registers.set_BC(0x0013);
+                self.registers.set_DE(0x00D8);
+                self.registers.set_HL(0x014D);
+            },
+            Model::Sgb => {
+                self.registers.set_AF(0x0100);
+                self.registers.set_BC(0x0014);
+                self.registers.set_DE(0x0000);
+                self.registers.set_HL(0xC060);
+            },
+        }
         self.registers.SP = 0xFFFE;
         self.registers.write_PC(0x0100);
 
@@ -137,7 +182,7 @@ impl CPU {
         memory.write(NR44_ADDR, 0xBF);  // NR43
         memory.write(NR50_ADDR, 0x77);  // NR50
         memory.write(NR51_ADDR, 0xF3);  // NR51
-        memory.write(NR52_ADDR, 0xF1);  // NR52, GB, 0xF0-SGB
+        memory.write(NR52_ADDR, if model == Model::Sgb { 0xF0 } else { 0xF1 });  // NR52, GB, 0xF0-SGB
         memory.write(LCDC_ADDR, 0x91);  // LCDC
         memory.write(SCY_ADDR, 0x00);  // SCY
         memory.write(SCX_ADDR, 0x00);  // SCX
@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::constants::*;
+use crate::joypad::Joypad;
+
+/// Receives every completed frame, called by the PPU when it enters VBlank.
+pub trait VideoSink {
+    /// `frame` holds `SCREEN_WIDTH * SCREEN_HEIGHT` grayscale shades, row-major.
+    fn present(&mut self, frame: &[u8]);
+}
+
+/// Receives audio from the APU each time a buffer of samples is complete.
+pub trait AudioSink {
+    /// `samples` are interleaved stereo pairs at `TARGET_SAMPLE_RATE`.
+    fn queue(&mut self, samples: &[f32]);
+}
+
+/// Polled by the joypad every `JOYPAD_POLL_TICKS` to update the pressed buttons.
+pub trait InputSource {
+    /// Returns true when the host asked the emulator to quit.
+    fn poll(&mut self, joypad: &mut Joypad) -> bool;
+}
+
+/// Feeds the Pocket Camera's sensor, asked for a new image on every capture.
+pub trait ImageSource {
+    /// Fills `image` with `CAMERA_WIDTH * CAMERA_HEIGHT` grayscale values, row-major, 0 black to 255 white.
+    fn capture(&mut self, image: &mut [u8]);
+}
+
+pub struct NullVideo;
+
+impl VideoSink for NullVideo {
+    fn present(&mut self, _frame: &[u8]) {}
+}
+
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn queue(&mut self, _samples: &[f32]) {}
+}
+
+/// Leaves the joypad untouched, so buttons can still be set through `Gameboy::set_button`.
+pub struct NullInput;
+
+impl InputSource for NullInput {
+    fn poll(&mut self, _joypad: &mut Joypad) -> bool {
+        false
+    }
+}
+
+/// A flat gray sensor image, for when no images are given.
+pub struct NullImage;
+
+impl ImageSource for NullImage {
+    fn capture(&mut self, image: &mut [u8]) {
+        image.fill(0x80);
+    }
+}
+
+/// Writes every frame as a numbered binary PGM image into a directory.
+pub struct PgmFrameWriter {
+    dir: PathBuf,
+    frame_i: u64,
+    error: Option<io::Error>,
+}
+
+impl PgmFrameWriter {
+    pub fn new(dir: &Path) -> io::Result<PgmFrameWriter> {
+        std::fs::create_dir_all(dir)?;
+        Ok(PgmFrameWriter {
+            dir: dir.to_path_buf(),
+            frame_i: 0,
+            error: None,
+        })
+    }
+
+    /// The first error hit while writing frames, after which writing stops.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn write_frame(&self, frame: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(format!("frame_{:06}.pgm", self.frame_i));
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P5\n{SCREEN_WIDTH} {SCREEN_HEIGHT}\n255\n")?;
+        file.write_all(frame)?;
+        file.flush()
+    }
+}
+
+impl VideoSink for PgmFrameWriter {
+    fn present(&mut self, frame: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write_frame(frame) {
+            self.error = Some(e);
+        }
+        self.frame_i += 1;
+    }
+}
+
+/// Writes the audio stream into a 32-bit float stereo WAV file.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+    error: Option<io::Error>,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path) -> io::Result<WavWriter> {
+        let mut writer = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            data_len: 0,
+            error: None,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /// The first error hit while writing samples, after which writing stops.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let channels: u16 = 2;
+        let bytes_per_sample: u16 = 4;
+        let byte_rate = TARGET_SAMPLE_RATE as u32 * channels as u32 * bytes_per_sample as u32;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.write_all(b"WAVEfmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&3u16.to_le_bytes())?; // IEEE float
+        self.file.write_all(&channels.to_le_bytes())?;
+        self.file.write_all(&(TARGET_SAMPLE_RATE as u32).to_le_bytes())?;
+        self.file.write_all(&byte_rate.to_le_bytes())?;
+        self.file.write_all(&(channels * bytes_per_sample).to_le_bytes())?;
+        self.file.write_all(&(bytes_per_sample * 8).to_le_bytes())?;
+        self.file.write_all(b"data")?;
+        self.file.write_all(&self.data_len.to_le_bytes())
+    }
+
+    /// Patches the chunk sizes in the header so the file is playable.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn queue(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        for sample in samples {
+            if let Err(e) = self.file.write_all(&sample.to_le_bytes()) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        self.data_len += (samples.len() * 4) as u32;
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -1,17 +1,98 @@
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::{Duration, Instant};
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
 
-use crate::constants::{IF_ADDR, IE_ADDR, SB_ADDR, SC_ADDR, TIMA_ADDR, TMA_ADDR, TAC_ADDR};
+use crate::cartridge::LoadError;
+use crate::constants::{IF_ADDR, IE_ADDR, SB_ADDR, SC_ADDR, CYCLES_PER_FRAME, REWIND_INTERVAL_FRAMES};
+use crate::constants::{FRAMES_PER_SECOND, MIN_SPEED, MAX_SPEED, SPEED_PRESETS};
 use crate::cpu::{CPU, DEBUG};
+use crate::frontend::{AudioSink, ImageSource, InputSource, NullAudio, NullInput, NullVideo, VideoSink};
 use crate::memory::AddressSpace;
 use crate::graphics::PPU;
 use crate::interrupt::Interrupt;
-use crate::joypad::Joypad;
+use crate::joypad::{Button, Joypad};
+use crate::mappers::MapperKind;
+use crate::rewind::RewindBuffer;
+use crate::savestate::{StateError, StateReader, StateWriter, SAVE_STATE_MAGIC, SAVE_STATE_VERSION};
 use crate::sound::APU;
 
+/// Hardware model, which only changes the register values left behind by the boot ROM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    Dmg,
+    Mgb,
+    Sgb,
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            _ => Err(format!("unknown model '{s}' (expected dmg, mgb or sgb)")),
+        }
+    }
+}
+
+/// Emulation speed relative to real hardware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    Multiplier(f32),
+    Unlimited,
+}
+
+impl Speed {
+    /// The next preset above this speed, ending at `Unlimited`.
+    pub fn faster(self) -> Speed {
+        match self {
+            Speed::Multiplier(multiplier) => SPEED_PRESETS.iter()
+                .find(|&&preset| preset > multiplier)
+                .map_or(Speed::Unlimited, |&preset| Speed::Multiplier(preset)),
+            Speed::Unlimited => Speed::Unlimited,
+        }
+    }
+
+    /// The next preset below this speed, ending at the slowest one.
+    pub fn slower(self) -> Speed {
+        let multiplier = match self {
+            Speed::Multiplier(multiplier) => multiplier,
+            Speed::Unlimited => f32::INFINITY,
+        };
+        let preset = SPEED_PRESETS.iter().rev().find(|&&preset| preset < multiplier).unwrap_or(&SPEED_PRESETS[0]);
+        Speed::Multiplier(*preset)
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("unlimited") {
+            return Ok(Speed::Unlimited);
+        }
+        let multiplier: f32 = s.strip_suffix('x').unwrap_or(s).parse()
+            .map_err(|_| format!("unknown speed '{s}' (expected a multiplier or unlimited)"))?;
+        if !(MIN_SPEED..=MAX_SPEED).contains(&multiplier) {
+            return Err(format!("speed must be between {MIN_SPEED}x and {MAX_SPEED}x, got {s}"));
+        }
+        Ok(Speed::Multiplier(multiplier))
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Speed::Multiplier(multiplier) => write!(f, "{multiplier}x"),
+            Speed::Unlimited => write!(f, "unlimited"),
+        }
+    }
+}
 
 pub struct Gameboy {
     cpu: CPU,
@@ -19,39 +100,207 @@ pub struct Gameboy {
     ppu: PPU,
     joypad: Joypad,
     apu: APU,
+    video: Box<dyn VideoSink>,
+    audio: Box<dyn AudioSink>,
+    input: Box<dyn InputSource>,
+    quit_requested: bool,
+    model: Model,
+    serial_output: Vec<u8>,
+    rewind: RewindBuffer,
+    rewind_interval: u32,
+    frames_since_snapshot: u32,
+    /// Frames the restored snapshot is still shown for, so rewinding plays
+    /// back at the speed the snapshots were taken.
+    rewind_hold_frames: u32,
+    rewinding: bool,
+    speed: Speed,
+    frame_deadline: Option<Instant>,
+}
+
+impl Default for Gameboy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Gameboy {
+    /// A Game Boy running as fast as the host allows, until `set_speed` paces it.
     pub fn new() -> Gameboy {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let mut event_pump = sdl_context.event_pump().unwrap();
-
-        let audio_subsystem = sdl_context.audio().unwrap();
-
-        Gameboy {
+        let mut gb = Gameboy {
             cpu: CPU::new(),
             memory: AddressSpace::new(),
-            ppu: PPU::new(video_subsystem, 3.0),
-            joypad: Joypad::new(event_pump),
-            apu: APU::new(audio_subsystem),
-        }
+            ppu: PPU::new(),
+            joypad: Joypad::new(),
+            apu: APU::new(),
+            video: Box::new(NullVideo),
+            audio: Box::new(NullAudio),
+            input: Box::new(NullInput),
+            quit_requested: false,
+            model: Model::Dmg,
+            serial_output: Vec::new(),
+            rewind: RewindBuffer::new(0),
+            rewind_interval: REWIND_INTERVAL_FRAMES,
+            frames_since_snapshot: 0,
+            rewind_hold_frames: 0,
+            rewinding: false,
+            speed: Speed::Unlimited,
+            frame_deadline: None,
+        };
+        gb.set_speed(Speed::Unlimited);
+        gb
+    }
+
+    /// Selects the model emulated by the next `power_on`.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Sets where battery saves live. Must be called before loading a game.
+    pub fn set_save_dir(&mut self, dir: &Path) {
+        self.memory.set_save_dir(dir);
+    }
+
+    pub fn set_video_sink(&mut self, video: Box<dyn VideoSink>) {
+        self.video = video;
+    }
+
+    pub fn set_audio_sink(&mut self, audio: Box<dyn AudioSink>) {
+        self.audio = audio;
     }
 
-    pub fn load_game(&mut self, path: &Path) {
-        let mut file = match File::open(path) {
-            Err(er) => panic!("Error found: '{}'", er),
-            Ok(file) => file,
+    /// Sets how fast `run_frame` runs. Audio is resampled to keep up.
+    /// Multipliers are clamped to `MIN_SPEED..=MAX_SPEED`, and NaN means 1x.
+    pub fn set_speed(&mut self, speed: Speed) {
+        let speed = match speed {
+            Speed::Multiplier(multiplier) if multiplier.is_nan() => Speed::Multiplier(1.0),
+            Speed::Multiplier(multiplier) => Speed::Multiplier(multiplier.clamp(MIN_SPEED, MAX_SPEED)),
+            Speed::Unlimited => Speed::Unlimited,
         };
+        self.speed = speed;
+        // Unlimited runs keep sampling as if at the top speed, and the audio
+        // sink drops whatever it cannot play.
+        self.apu.set_speed(match speed {
+            Speed::Multiplier(multiplier) => multiplier,
+            Speed::Unlimited => MAX_SPEED,
+        });
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Sets how many bytes of snapshots rewinding may keep. Zero, the default, disables it.
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.rewind.set_budget(bytes);
+    }
+
+    /// Sets how many frames pass between rewind snapshots.
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.rewind_interval = frames.max(1);
+    }
+
+    /// While rewinding, `run_frame` steps back through the snapshots instead of emulating.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+        if !rewinding {
+            self.rewind_hold_frames = 0;
+        }
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    pub fn set_input_source(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
+    }
+
+    pub fn load_game(&mut self, path: &Path) -> Result<Vec<LoadError>, LoadError> {
+        let mut file = File::open(path)?;
         let mut buf = Vec::new();
-        let _content = match file.read_to_end(&mut buf) {
-            Err(er) => panic!("Error found: '{}'", er),
-            Ok(file) => file,
-        };
-        match self.memory.load_rom(buf) {
-            Ok(x) => x,
-            Err(s) => panic!("Failed to load game: {s}"),
+        file.read_to_end(&mut buf)?;
+        self.load_rom(buf)
+    }
+
+    /// Loads a ROM, returning the problems that did not stop it from loading,
+    /// such as a bad header checksum.
+    pub fn load_rom(&mut self, game_bytes: Vec<u8>) -> Result<Vec<LoadError>, LoadError> {
+        let warnings = self.memory.load_rom(game_bytes)?;
+        self.rewind.clear();
+        Ok(warnings)
+    }
+
+    /// Serializes the whole machine, excluding the frontend sinks.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write_slice(SAVE_STATE_MAGIC);
+        state.write_u16(SAVE_STATE_VERSION);
+        state.write_u8(self.model as u8);
+        self.cpu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        self.ppu.save_state(&mut state);
+        self.apu.save_state(&mut state);
+        self.joypad.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    /// Restores a state made by `save_state` for the loaded game. The machine
+    /// is left untouched when the state is rejected, unless putting it back
+    /// fails too, in which case that error is returned instead.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_err() {
+            self.restore_state(&backup)?;
+        }
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        let mut magic = [0; 4];
+        state.read_slice(&mut magic).map_err(|_| StateError::BadMagic)?;
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = state.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        self.model = match state.read_u8()? {
+            0 => Model::Dmg,
+            1 => Model::Mgb,
+            2 => Model::Sgb,
+            _ => return Err(StateError::Invalid("model")),
         };
+        self.cpu.load_state(&mut state)?;
+        self.memory.load_state(&mut state)?;
+        self.ppu.load_state(&mut state)?;
+        self.apu.load_state(&mut state)?;
+        self.joypad.load_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        Ok(())
+    }
+
+    /// Path of a numbered save state slot, next to the game's battery save.
+    pub fn state_slot_path(&self, slot: u8) -> PathBuf {
+        self.memory.game_save_dir().join(format!("state{slot}.bin"))
+    }
+
+    pub fn save_state_slot(&self, slot: u8) -> Result<(), StateError> {
+        let path = self.state_slot_path(slot);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.save_state())?;
+        Ok(())
+    }
+
+    pub fn load_state_slot(&mut self, slot: u8) -> Result<(), StateError> {
+        let data = std::fs::read(self.state_slot_path(slot))?;
+        self.load_state(&data)
     }
 
     fn check_interrupts(&self) -> Option<Interrupt> {
@@ -82,58 +331,320 @@ impl Gameboy {
     }
 
     pub fn power_on(&mut self) {
-        self.cpu.boot(&mut self.memory);
-        loop {
-            let start_t = self.cpu.clock;
-            let t0 = Instant::now();
-            if DEBUG {
-                println!("{}", self.cpu);
-            }
-            // let formatted = self.memory.oam.iter()
-            //     .map(|byte| format!("{:02X}", byte))
-            //     .collect::<Vec<_>>()
-            //     .join("|");
-
-            // println!("{} ; OAM: |{}", self.cpu, formatted);
-            // println!("{}", self.cpu);
-            // println!("{} | int div: {} | TIMA: {} | TMA: {} | TAC: {} | IE: {} | IF: {}",self.cpu, self.memory.internal_div, self.memory.read(TIMA_ADDR), self.memory.read(TMA_ADDR), self.memory.read(TAC_ADDR), self.memory.read(IE_ADDR), self.memory.read(IF_ADDR));
-            if self.cpu.enable_interrupts_next_instr {
-                self.cpu.master_interrupt_enable = true;
-                self.cpu.enable_interrupts_next_instr = false;
-            }
-            if let Some(interrupt) = self.check_interrupts() {
-                if self.cpu.master_interrupt_enable {
-                    if self.cpu.is_halted() {
-                        self.cpu.quit_halt();
-                    }
-                    self.serve_interrupt(interrupt);
-                } else {
-                    if self.cpu.is_halted() {
-                        self.cpu.quit_halt();
-                    }
+        self.cpu.boot(&mut self.memory, self.model);
+    }
+
+    /// Executes a single instruction (serving a pending interrupt first) and
+    /// advances every peripheral by the same number of ticks, which is returned.
+    pub fn step_instruction(&mut self) -> u8 {
+        let start_t = self.cpu.clock;
+        if DEBUG {
+            println!("{}", self.cpu);
+        }
+        if self.cpu.enable_interrupts_next_instr {
+            self.cpu.master_interrupt_enable = true;
+            self.cpu.enable_interrupts_next_instr = false;
+        }
+        if let Some(interrupt) = self.check_interrupts() {
+            if self.cpu.master_interrupt_enable {
+                if self.cpu.is_halted() {
+                    self.cpu.quit_halt();
+                }
+                self.serve_interrupt(interrupt);
+            } else {
+                if self.cpu.is_halted() {
+                    self.cpu.quit_halt();
                 }
             }
+        }
 
-            let opcode_byte = self.cpu.fetch(&self.memory);
-            let (opcode_dict, opcode) = self.cpu.decode(opcode_byte, &self.memory);
-            let remaining_ticks = self.cpu.execute(opcode, opcode_dict, &mut self.memory);
-            let nticks = (self.cpu.clock - start_t) as u8  + remaining_ticks;
-            
-            self.cpu.tick(remaining_ticks);
-            self.ppu.tick(nticks, &mut self.memory);
-            let quit = self.joypad.tick(nticks, &mut self.memory);
-            self.memory.tick(nticks);
-            self.apu.tick(nticks, &mut self.memory);
-            if self.memory.read(SC_ADDR) == 0x81 {
-                if !DEBUG {
-                    // println!("SERIAL: {}", std::char::from_u32(self.memory.read(SB_ADDR) as u32).unwrap_or('?'));
-                }
-                self.memory.write(SC_ADDR, 0);
+        let opcode_byte = self.cpu.fetch(&self.memory);
+        let (opcode_dict, opcode) = self.cpu.decode(opcode_byte, &self.memory);
+        let remaining_ticks = self.cpu.execute(opcode, opcode_dict, &mut self.memory);
+        let nticks = (self.cpu.clock - start_t) as u8  + remaining_ticks;
+
+        self.cpu.tick(remaining_ticks);
+        self.ppu.tick(nticks, &mut self.memory, self.video.as_mut());
+        self.quit_requested |= self.joypad.tick(nticks, &mut self.memory, self.input.as_mut());
+        self.memory.tick(nticks);
+        self.apu.tick(nticks, &mut self.memory, self.audio.as_mut());
+        if self.memory.read(SC_ADDR) == 0x81 {
+            self.serial_output.push(self.memory.read(SB_ADDR));
+            self.memory.write(SC_ADDR, 0);
+        }
+        nticks
+    }
+
+    /// Runs whole instructions until at least `cycles` ticks have elapsed and
+    /// returns the ticks actually run, which may overshoot by one instruction.
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let mut ticks = 0;
+        while ticks < cycles {
+            ticks += self.step_instruction() as u64;
+        }
+        ticks
+    }
+
+    /// Runs until the PPU enters VBlank and returns true. Returns false after
+    /// a frame's worth of ticks without one, as happens while the LCD is off.
+    /// In rewind mode it restores the previous snapshot every `rewind_interval`
+    /// frames instead, returning false once there are none left.
+    /// Either way, it then waits until the frame is due at the current speed.
+    pub fn run_frame(&mut self) -> bool {
+        let frame_done = if self.rewinding {
+            self.rewind_frame()
+        } else {
+            self.emulate_frame()
+        };
+        self.pace_frame();
+        frame_done
+    }
+
+    fn emulate_frame(&mut self) -> bool {
+        self.record_rewind_snapshot();
+        self.ppu.take_frame_ready();
+        let mut ticks = 0;
+        while ticks < CYCLES_PER_FRAME {
+            ticks += self.step_instruction() as u64;
+            if self.ppu.take_frame_ready() {
+                return true;
             }
-            if quit {
-                self.memory.quit();
+        }
+        false
+    }
+
+    /// Sleeps until the end of the current frame's time slot. A frame that
+    /// overran its slot restarts the schedule instead of being caught up on.
+    fn pace_frame(&mut self) {
+        let multiplier = match self.speed {
+            Speed::Multiplier(multiplier) => multiplier,
+            Speed::Unlimited => {
+                self.frame_deadline = None;
                 return;
-            }
+            },
+        };
+        let frame_time = Duration::from_secs_f64(1.0 / (FRAMES_PER_SECOND * multiplier as f64));
+        let now = Instant::now();
+        let deadline = self.frame_deadline.unwrap_or(now) + frame_time;
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+            self.frame_deadline = Some(deadline);
+        } else {
+            self.frame_deadline = Some(now);
+        }
+    }
+
+    fn record_rewind_snapshot(&mut self) {
+        if !self.rewind.is_enabled() {
+            return;
+        }
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot >= self.rewind_interval {
+            self.frames_since_snapshot = 0;
+            let state = self.save_state();
+            self.rewind.push(state);
+        }
+    }
+
+    fn rewind_frame(&mut self) -> bool {
+        self.quit_requested |= self.input.poll(&mut self.joypad);
+        if self.rewind_hold_frames > 0 {
+            self.rewind_hold_frames -= 1;
+            self.video.present(self.ppu.framebuffer());
+            return true;
+        }
+        let state = match self.rewind.pop() {
+            Some(state) => state,
+            None => return false,
+        };
+        // Snapshots come from `save_state`, so there is no need for the
+        // backup `load_state` takes against foreign states.
+        if self.restore_state(&state).is_err() {
+            self.rewind.clear();
+            return false;
         }
+        self.frames_since_snapshot = 0;
+        self.rewind_hold_frames = self.rewind_interval - 1;
+        self.video.present(self.ppu.framebuffer());
+        true
     }
-}
\ No newline at end of file
+
+    /// Ticks elapsed since the machine was created.
+    pub fn clock(&self) -> u64 {
+        self.cpu.clock
+    }
+
+    /// Returns whether the PPU entered VBlank since the last call, for callers
+    /// driving the machine with `step_instruction` or `run_cycles`.
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.ppu.take_frame_ready()
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.ppu.framebuffer()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.joypad.set_button(button, pressed);
+    }
+
+    /// Whether the cartridge's rumble motor is running, for frontends with force feedback.
+    pub fn rumble(&self) -> bool {
+        self.memory.rumble()
+    }
+
+    /// Tilts the console for cartridges with an accelerometer. `x` is positive to
+    /// the right and `y` towards the player, both in g, with 0 held flat.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.memory.set_tilt(x, y);
+    }
+
+    /// Sets where the Pocket Camera's sensor images come from. The source
+    /// belongs to the loaded cartridge, so set it after `load_rom`.
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.memory.set_image_source(source);
+    }
+
+    /// Forces the mapper of ROMs loaded afterwards, for unlicensed cartridges
+    /// the heuristics miss or headers that name the wrong one.
+    pub fn set_mapper_override(&mut self, mapper: Option<MapperKind>) {
+        self.memory.set_mapper_override(mapper);
+    }
+
+    /// Every byte the game sent over the serial port, as test ROMs use it to report results.
+    pub fn serial_output(&self) -> &[u8] {
+        &self.serial_output
+    }
+
+    /// Whether the input source asked to quit. The caller is expected to call `quit`.
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    /// Persists battery-backed cartridge RAM before shutting down.
+    pub fn quit(&self) -> std::io::Result<()> {
+        self.memory.quit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{CartridgeHeader, NINTENDO_LOGO};
+    use crate::constants::{CARTRIDGE_TYPE_ADDR, HEADER_CHECKSUM_ADDR, NINTENDO_LOGO_ADDR, RAM_SIZE_ADDR, TITLE_ADDR};
+
+    /// A 32 KiB ROM titled `title` with a valid header checksum, which spins
+    /// on a `JR -2` at the entry point.
+    fn test_rom(title: &str) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        rom[NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE_ADDR.start..TITLE_ADDR.start + title.len()].copy_from_slice(title.as_bytes());
+        rom[HEADER_CHECKSUM_ADDR] = CartridgeHeader::parse(&rom).unwrap().computed_header_checksum;
+        rom
+    }
+
+    /// A powered on Game Boy one frame into `test_rom(title)`.
+    fn running_gameboy(title: &str) -> Gameboy {
+        let mut gb = Gameboy::new();
+        assert!(gb.load_rom(test_rom(title)).unwrap().is_empty());
+        gb.power_on();
+        gb.run_frame();
+        gb
+    }
+
+    #[test]
+    fn bad_header_checksum_is_only_a_warning() {
+        let mut rom = test_rom("GAME A");
+        rom[HEADER_CHECKSUM_ADDR] ^= 0xFF;
+        let mut gb = Gameboy::new();
+        let warnings = gb.load_rom(rom).unwrap();
+        assert!(matches!(warnings[..], [LoadError::ChecksumMismatch { .. }]));
+        assert!(gb.memory.game_save_dir().ends_with("GAME A"));
+    }
+
+    #[test]
+    fn unreadable_battery_save_is_only_a_warning() {
+        let mut rom = test_rom("GAME B");
+        rom[CARTRIDGE_TYPE_ADDR] = 0x03;
+        rom[RAM_SIZE_ADDR] = 0x02;
+        rom[HEADER_CHECKSUM_ADDR] = CartridgeHeader::parse(&rom).unwrap().computed_header_checksum;
+        let dir = std::env::temp_dir().join(format!("rusting_empty_gameboy_{}", std::process::id()));
+        let mut gb = Gameboy::new();
+        gb.set_save_dir(&dir);
+        // No save yet is not worth a warning.
+        assert!(gb.load_rom(rom.clone()).unwrap().is_empty());
+
+        // A directory in place of SAVE.bin cannot be read as one.
+        std::fs::create_dir_all(dir.join("GAME B").join("SAVE.bin")).unwrap();
+        let warnings = gb.load_rom(rom);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(warnings.unwrap()[..], [LoadError::UnreadableSave(_)]));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut gb = running_gameboy("GAME A");
+        let state = gb.save_state();
+        gb.run_frame();
+        assert_ne!(gb.save_state(), state);
+        gb.load_state(&state).unwrap();
+        assert_eq!(gb.save_state(), state);
+    }
+
+    #[test]
+    fn rejects_states_of_other_versions() {
+        let mut gb = running_gameboy("GAME A");
+        let mut state = gb.save_state();
+        gb.run_frame();
+        let before = gb.save_state();
+        state[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(gb.load_state(&state), Err(StateError::UnsupportedVersion(v)) if v == SAVE_STATE_VERSION + 1));
+        state[..4].copy_from_slice(b"NOPE");
+        assert!(matches!(gb.load_state(&state), Err(StateError::BadMagic)));
+        assert_eq!(gb.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_states_of_other_games() {
+        let state = running_gameboy("GAME A").save_state();
+        let mut gb = running_gameboy("GAME B");
+        let before = gb.save_state();
+        assert!(matches!(gb.load_state(&state), Err(StateError::WrongGame)));
+        assert_eq!(gb.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_states() {
+        let mut gb = running_gameboy("GAME A");
+        let mut state = gb.save_state();
+        gb.run_frame();
+        let before = gb.save_state();
+        assert!(matches!(gb.load_state(&state[..state.len() - 1]), Err(StateError::Truncated)));
+        state.push(0);
+        assert!(matches!(gb.load_state(&state), Err(StateError::Invalid("length"))));
+        assert_eq!(gb.save_state(), before);
+    }
+
+    #[test]
+    fn rewind_holds_each_snapshot_for_the_interval() {
+        let mut gb = running_gameboy("GAME A");
+        gb.set_rewind_budget(1 << 20);
+        gb.set_rewind_interval(4);
+        for _ in 0..11 {
+            gb.run_frame();
+        }
+        // Snapshots are taken at the start of frames 4, 8 and 12.
+        let state = gb.save_state();
+        gb.run_frame();
+
+        gb.set_rewinding(true);
+        assert!(gb.run_frame());
+        assert!(gb.save_state() == state);
+        let mut frames = 1;
+        while gb.run_frame() {
+            frames += 1;
+        }
+        assert_eq!(frames, 3 * 4);
+    }
+}
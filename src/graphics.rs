@@ -1,19 +1,11 @@
-extern crate sdl2;
-
-use sdl2::pixels::{Color, PixelFormatEnum};
-use sdl2::render::TextureCreator;
-use sdl2::video::WindowContext;
-use sdl2::VideoSubsystem;
 use std::collections::HashSet;
-use std::time::Duration;
 use std::cmp::{min, max};
-use sdl2::rect::Point;
-use std::time::Instant;
-use std::convert::Into;
 
 use crate::constants::*;
+use crate::frontend::VideoSink;
 use crate::interrupt::Interrupt;
 use crate::memory::AddressSpace;
+use crate::savestate::{StateError, StateReader, StateWriter};
 use crate::sprites::*;
 
 
@@ -84,44 +76,96 @@ pub struct PPU {
     line_objects: Vec<SpriteData>,
     mode: PPUMode,
     tick_i: u64,
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
-    texture_creator: TextureCreator<WindowContext>,
     render_window_on_cur_frame: bool,
     wly: usize,
     stat_flag: bool,
     past_cycle_disabled: bool,
-    frame_start_t: Instant,
     past_tick_lyc: Option<u8>,
     img: [u8; SCREEN_HEIGHT * SCREEN_WIDTH],
+    frame_ready: bool,
 }
 
 impl PPU {
-    pub fn new(sdl_subsystem: VideoSubsystem, window_scale: f32) -> PPU {
-        let window = sdl_subsystem
-            .window("rust-sdl2 demo: Video", (SCREEN_WIDTH as f32 * window_scale) as u32, (SCREEN_HEIGHT as f32 * window_scale) as u32)
-            .position_centered()
-            .opengl()
-            .build()
-            .map_err(|e| e.to_string()).unwrap();
-        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string()).unwrap();
-        canvas.set_scale(window_scale, window_scale);
-        let texture_creator = canvas.texture_creator();
+    pub fn new() -> PPU {
         PPU {
             dot: 0,
             ly: 0,
             line_objects: Vec::new(),
             mode: PPUMode::OAMScan,
             tick_i: 0,
-            canvas,
             render_window_on_cur_frame: false,
             wly: 0,
             past_cycle_disabled: false,
-            texture_creator,
-            frame_start_t: Instant::now(),
             stat_flag: false,
             past_tick_lyc: None,
             img: [0; SCREEN_HEIGHT * SCREEN_WIDTH],
+            frame_ready: false,
+        }
+    }
+
+    /// Grayscale shades of the last rendered frame, one byte per pixel, row-major.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.img
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.dot);
+        state.write_u8(self.ly);
+        state.write_u8(self.line_objects.len() as u8);
+        for sprite in self.line_objects.iter() {
+            sprite.save_state(state);
         }
+        state.write_u8(match self.mode {
+            PPUMode::HBlank => 0,
+            PPUMode::VBlank => 1,
+            PPUMode::OAMScan => 2,
+            PPUMode::Drawing => 3,
+        });
+        state.write_u64(self.tick_i);
+        state.write_bool(self.render_window_on_cur_frame);
+        state.write_u32(self.wly as u32);
+        state.write_bool(self.stat_flag);
+        state.write_bool(self.past_cycle_disabled);
+        state.write_bool(self.past_tick_lyc.is_some());
+        state.write_u8(self.past_tick_lyc.unwrap_or(0));
+        state.write_slice(&self.img);
+        state.write_bool(self.frame_ready);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.dot = state.read_u16()?;
+        self.ly = state.read_u8()?;
+        let num_objects = state.read_u8()?;
+        if num_objects > 10 {
+            return Err(StateError::Invalid("PPU line object count"));
+        }
+        self.line_objects.clear();
+        for _ in 0..num_objects {
+            self.line_objects.push(SpriteData::load_state(state)?);
+        }
+        self.mode = match state.read_u8()? {
+            0 => PPUMode::HBlank,
+            1 => PPUMode::VBlank,
+            2 => PPUMode::OAMScan,
+            3 => PPUMode::Drawing,
+            _ => return Err(StateError::Invalid("PPU mode")),
+        };
+        self.tick_i = state.read_u64()?;
+        self.render_window_on_cur_frame = state.read_bool()?;
+        self.wly = state.read_u32()? as usize;
+        self.stat_flag = state.read_bool()?;
+        self.past_cycle_disabled = state.read_bool()?;
+        let has_past_tick_lyc = state.read_bool()?;
+        let past_tick_lyc = state.read_u8()?;
+        self.past_tick_lyc = if has_past_tick_lyc { Some(past_tick_lyc) } else { None };
+        state.read_slice(&mut self.img)?;
+        self.frame_ready = state.read_bool()?;
+        Ok(())
+    }
+
+    /// Returns whether a full frame was rendered since the last call, clearing the flag.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
     }
 
     fn check_stat_irq(&self, memory: &AddressSpace) -> bool {
@@ -142,35 +186,6 @@ impl PPU {
         }
     } 
 
-    fn render_current_frame(&mut self) {
-        let mut texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).map_err(|e| e.to_string()).unwrap();
-        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in 0..SCREEN_HEIGHT {
-                for x in 0..SCREEN_WIDTH {
-                    let offset = y * pitch + x * 3;
-                    let value = self.img[y * SCREEN_WIDTH + x];
-                    buffer[offset] = value;
-                    buffer[offset + 1] = value;
-                    buffer[offset + 2] = value;
-                }
-            }
-        }).unwrap();
-        
-        self.canvas.clear();
-        self.canvas.copy(&texture, None, Some(sdl2::rect::Rect::new(0, 0, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32))).unwrap();
-        // self.canvas.copy_ex(
-        //     &texture,
-        //     None,
-        //     Some(sdl2::rect::Rect::new(0, 0, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)),
-        //     0.0,
-        //     None,
-        //     false,
-        //     false,
-        // ).unwrap();
-        self.canvas.present();
-    }
-
-
     fn oam_scan_step(&mut self, memory: &mut AddressSpace) {
         if !self.render_window_on_cur_frame && self.ly == wy(memory) as u8 && window_enabled(memory) {
             self.render_window_on_cur_frame = true;
@@ -178,17 +193,6 @@ impl PPU {
         }
         if self.dot == 0 {
             memory.ppu_write_LY_update_STAT(self.ly);
-            if self.ly == 0 {
-                let raw_frame_time_seconds = self.frame_start_t.elapsed().as_secs_f64();
-                let time_to_wait = 1.0/59.7 - raw_frame_time_seconds - 0.00006;
-                if time_to_wait > 0.0 {
-                    std::thread::sleep(Duration::from_secs_f64(time_to_wait));
-                }
-                let frame_time_seconds = self.frame_start_t.elapsed().as_secs_f64();
-                let fps = 1.0 / frame_time_seconds;
-                // println!("FPS: {fps:.1}, raw frame time: {:.3} ms ({:.1} FPS)", raw_frame_time_seconds * 1000.0, 1.0 / raw_frame_time_seconds);
-                self.frame_start_t = Instant::now();
-            }
         }
         if self.dot == 80 {
             self.mode = PPUMode::Drawing;
@@ -228,7 +232,7 @@ impl PPU {
         self.dot += 1;
     }
 
-    fn hblank_step(&mut self, memory: &mut AddressSpace) {
+    fn hblank_step(&mut self, memory: &mut AddressSpace, video: &mut dyn VideoSink) {
         if self.dot == NUM_DOTS_PER_LINE { 
             self.dot = 0; 
             self.ly += 1; 
@@ -237,7 +241,8 @@ impl PPU {
                 memory.unlock_oam();
                 memory.request_interrupt(Interrupt::VBlank);
 
-                self.render_current_frame()
+                self.frame_ready = true;
+                video.present(&self.img);
             } else { 
                 if self.render_window_on_cur_frame && wx(memory) <= 166 {
                     self.wly += 1;
@@ -271,11 +276,11 @@ impl PPU {
         }
     }
 
-    fn set_next_mode(&mut self, memory: &mut AddressSpace) {
+    fn set_next_mode(&mut self, memory: &mut AddressSpace, video: &mut dyn VideoSink) {
         match self.mode {
             PPUMode::OAMScan => self.oam_scan_step(memory),
             PPUMode::Drawing => self.drawing_step(memory),
-            PPUMode::HBlank => self.hblank_step(memory),
+            PPUMode::HBlank => self.hblank_step(memory, video),
             PPUMode::VBlank => self.vblank_step(memory),
         };
     }
@@ -293,7 +298,7 @@ impl PPU {
     }
     
 
-    fn single_tick(&mut self, memory: &mut AddressSpace) {
+    fn single_tick(&mut self, memory: &mut AddressSpace, video: &mut dyn VideoSink) {
         if !get_ppu_enabled(memory) {
             // self.mode = PPUMode::HBlank;
             self.stat_flag = false;
@@ -332,16 +337,16 @@ impl PPU {
                 memory.ppu_write_LY_update_STAT(self.ly)
             }
         }
-        self.set_next_mode(memory);
+        self.set_next_mode(memory, video);
 
         self.handle_stat(memory);
         self.past_tick_lyc = Some(lyc);
         self.tick_i += 1
     }
 
-    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace) {
+    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace, video: &mut dyn VideoSink) {
         for _ in 0..nticks {
-            self.single_tick(memory);
+            self.single_tick(memory, video);
         }
     }
 
@@ -573,19 +578,16 @@ impl PPU {
                 }
             }
             // color = bg_color;
-            let color_value = match (color_palette, color) {
-                // (ColorPalette::BGP, 4) => Color::RGB(255, 255, 255),
-                (ColorPalette::BGP, _) => BGP_palette[color as usize].into(),
-                (ColorPalette::OBP1, 0) => Color::RGB(0, 255, 0),
-                (ColorPalette::OBP0, 0) => Color::RGB(0, 255, 0),
-                (ColorPalette::OBP0, _) => obp0_palette[color as usize].into(),
-                (ColorPalette::OBP1, _) => obp1_palette[color as usize].into(),
+            let shade = match (color_palette, color) {
+                (ColorPalette::BGP, _) => BGP_palette[color as usize].shade(),
+                (ColorPalette::OBP1, 0) => 0,
+                (ColorPalette::OBP0, 0) => 0,
+                (ColorPalette::OBP0, _) => obp0_palette[color as usize].shade(),
+                (ColorPalette::OBP1, _) => obp1_palette[color as usize].shade(),
             };
-            
-            // self.canvas.set_draw_color(color_value);
-            // self.canvas.draw_point(Point::new(i as i32, line_j as i32)).unwrap();
+
             let idx = line_j * SCREEN_WIDTH + i;
-            self.img[idx] = color_value.r;
+            self.img[idx] = shade;
         }
     }
 }
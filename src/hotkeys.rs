@@ -0,0 +1,83 @@
+use device_query::{DeviceQuery, DeviceState, Keycode};
+
+const SLOT_KEYS: [Keycode; 10] = [
+    Keycode::Key0, Keycode::Key1, Keycode::Key2, Keycode::Key3, Keycode::Key4,
+    Keycode::Key5, Keycode::Key6, Keycode::Key7, Keycode::Key8, Keycode::Key9,
+];
+const SAVE_STATE_KEY: Keycode = Keycode::F5;
+const LOAD_STATE_KEY: Keycode = Keycode::F8;
+const REWIND_KEY: Keycode = Keycode::R;
+const FAST_FORWARD_KEY: Keycode = Keycode::Tab;
+const SLOWER_KEY: Keycode = Keycode::Minus;
+const FASTER_KEY: Keycode = Keycode::Equal;
+const TILT_LEFT_KEY: Keycode = Keycode::J;
+const TILT_RIGHT_KEY: Keycode = Keycode::L;
+const TILT_UP_KEY: Keycode = Keycode::I;
+const TILT_DOWN_KEY: Keycode = Keycode::K;
+/// How far the tilt moves towards the held direction on each poll, in g.
+const TILT_STEP: f32 = 0.1;
+
+pub enum Hotkey {
+    SelectSlot(u8),
+    SaveState,
+    LoadState,
+    Slower,
+    Faster,
+}
+
+/// Emulator hotkeys. `poll` reports each press once, while rewind and fast-forward act for as long as they are held.
+/// The tilt keys ease the console's tilt towards the held direction and back to flat once released.
+pub struct Hotkeys {
+    device_state: DeviceState,
+    held: Vec<Keycode>,
+    tilt: (f32, f32),
+}
+
+impl Hotkeys {
+    pub fn new() -> Hotkeys {
+        Hotkeys {
+            device_state: DeviceState::new(),
+            held: Vec::new(),
+            tilt: (0.0, 0.0),
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<Hotkey> {
+        let keys = self.device_state.get_keys();
+        let mut hotkeys = Vec::new();
+        for key in keys.iter().filter(|key| !self.held.contains(key)) {
+            if let Some(slot) = SLOT_KEYS.iter().position(|slot_key| slot_key == key) {
+                hotkeys.push(Hotkey::SelectSlot(slot as u8));
+            } else if *key == SAVE_STATE_KEY {
+                hotkeys.push(Hotkey::SaveState);
+            } else if *key == LOAD_STATE_KEY {
+                hotkeys.push(Hotkey::LoadState);
+            } else if *key == SLOWER_KEY {
+                hotkeys.push(Hotkey::Slower);
+            } else if *key == FASTER_KEY {
+                hotkeys.push(Hotkey::Faster);
+            }
+        }
+        self.held = keys;
+        let axis = |negative, positive| self.held.contains(&positive) as i8 as f32 - self.held.contains(&negative) as i8 as f32;
+        let target = (axis(TILT_LEFT_KEY, TILT_RIGHT_KEY), axis(TILT_UP_KEY, TILT_DOWN_KEY));
+        let ease = |current: f32, target: f32| current + (target - current).clamp(-TILT_STEP, TILT_STEP);
+        self.tilt = (ease(self.tilt.0, target.0), ease(self.tilt.1, target.1));
+        hotkeys
+    }
+
+    /// Whether the rewind key was held at the last `poll`.
+    pub fn rewind_held(&self) -> bool {
+        self.held.contains(&REWIND_KEY)
+    }
+
+    /// Tilt along the x and y axes in g, for `Gameboy::set_tilt`.
+    pub fn tilt(&self) -> (f32, f32) {
+        self.tilt
+    }
+
+    /// Whether the fast-forward key was held at the last `poll`.
+    pub fn fast_forward_held(&self) -> bool {
+        self.held.contains(&FAST_FORWARD_KEY)
+    }
+}
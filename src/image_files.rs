@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::constants::*;
+use crate::frontend::ImageSource;
+
+/// PGM or PNG images shown to the camera one per capture, looping back to the
+/// first after the last. Each is cropped to the sensor's aspect ratio and scaled.
+pub struct ImageFiles {
+    images: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl ImageFiles {
+    /// Opens a single image, giving a still picture, or every `.pgm` and `.png`
+    /// file in a directory in name order.
+    pub fn open(path: &Path) -> io::Result<ImageFiles> {
+        let paths = if path.is_dir() {
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<_>>()?;
+            paths.retain(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("pgm" | "png")));
+            paths.sort();
+            paths
+        } else {
+            vec![path.to_path_buf()]
+        };
+        if paths.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no PGM or PNG images in the directory"));
+        }
+        let images = paths.iter().map(|path| {
+            let (width, height, pixels) = read_image(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+            Ok(fit_to_sensor(width, height, &pixels))
+        }).collect::<io::Result<_>>()?;
+        Ok(ImageFiles { images, next: 0 })
+    }
+}
+
+impl ImageSource for ImageFiles {
+    fn capture(&mut self, image: &mut [u8]) {
+        image.copy_from_slice(&self.images[self.next]);
+        self.next = (self.next + 1) % self.images.len();
+    }
+}
+
+fn invalid_image(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a grayscale image as its width, height and row-major pixels.
+fn read_image(path: &Path) -> io::Result<(usize, usize, Vec<u8>)> {
+    let (width, height, pixels) = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => read_png(path)?,
+        _ => read_pgm(&std::fs::read(path)?)?,
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid_image("empty image"));
+    }
+    Ok((width, height, pixels))
+}
+
+/// Binary (P5) PGM with 8 or 16-bit samples.
+fn read_pgm(bytes: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    let mut pos = 0;
+    let mut next_token = || -> io::Result<&[u8]> {
+        loop {
+            while bytes.get(pos).is_some_and(|byte| byte.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            if bytes.get(pos) == Some(&b'#') {
+                while bytes.get(pos).is_some_and(|byte| *byte != b'\n') {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while bytes.get(pos).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            return if start == pos { Err(invalid_image("truncated PGM header")) } else { Ok(&bytes[start..pos]) };
+        }
+    };
+    if next_token()? != b"P5" {
+        return Err(invalid_image("not a binary PGM"));
+    }
+    let mut number = || -> io::Result<usize> {
+        std::str::from_utf8(next_token()?).ok().and_then(|token| token.parse().ok()).ok_or_else(|| invalid_image("bad PGM header"))
+    };
+    let (width, height, max_value) = (number()?, number()?, number()?);
+    if max_value == 0 || max_value > 0xFFFF {
+        return Err(invalid_image("bad PGM maximum value"));
+    }
+    // A single whitespace byte separates the header from the samples.
+    let data = bytes.get(pos + 1..).unwrap_or_default();
+    let sample_size = if max_value > 0xFF { 2 } else { 1 };
+    let data_size = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(sample_size))
+        .ok_or_else(|| invalid_image("bad PGM size"))?;
+    if data.len() < data_size {
+        return Err(invalid_image("truncated PGM data"));
+    }
+    let pixels = data.chunks_exact(sample_size).take(width * height).map(|sample| {
+        let value = sample.iter().fold(0, |value, byte| value << 8 | *byte as usize);
+        (value.min(max_value) * 255 / max_value) as u8
+    }).collect();
+    Ok((width, height, pixels))
+}
+
+fn read_png(path: &Path) -> io::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| invalid_image(e.to_string()))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| invalid_image(e.to_string()))?;
+    let channels = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()].chunks_exact(channels).map(|pixel| match pixel {
+        [gray] | [gray, _] => *gray,
+        [r, g, b, ..] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+        _ => unreachable!(),
+    }).collect();
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+/// Crops the centre of the image to the sensor's aspect ratio and scales it to
+/// `CAMERA_WIDTH * CAMERA_HEIGHT` by averaging the pixels under each sensor pixel.
+fn fit_to_sensor(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let (crop_width, crop_height) = if width * CAMERA_HEIGHT > height * CAMERA_WIDTH {
+        (height * CAMERA_WIDTH / CAMERA_HEIGHT, height)
+    } else {
+        (width, width * CAMERA_HEIGHT / CAMERA_WIDTH)
+    };
+    let (left, top) = ((width - crop_width) / 2, (height - crop_height) / 2);
+    let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+    for y in 0..CAMERA_HEIGHT {
+        let y0 = top + y * crop_height / CAMERA_HEIGHT;
+        let y1 = (top + (y + 1) * crop_height / CAMERA_HEIGHT).max(y0 + 1);
+        for x in 0..CAMERA_WIDTH {
+            let x0 = left + x * crop_width / CAMERA_WIDTH;
+            let x1 = (left + (x + 1) * crop_width / CAMERA_WIDTH).max(x0 + 1);
+            let sum: usize = (y0..y1).flat_map(|py| (x0..x1).map(move |px| pixels[py * width + px] as usize)).sum();
+            image[y * CAMERA_WIDTH + x] = (sum / ((y1 - y0) * (x1 - x0))) as u8;
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_8_and_16_bit_pgm() {
+        let (width, height, pixels) = read_pgm(b"P5\n# comment\n2 1\n255\n\x00\xFF").unwrap();
+        assert_eq!((width, height, pixels), (2, 1, vec![0x00, 0xFF]));
+        let (_, _, pixels) = read_pgm(b"P5 2 1 1000\n\x01\xF4\x03\xE8").unwrap();
+        assert_eq!(pixels, [127, 255]);
+    }
+
+    #[test]
+    fn rejects_malformed_pgm() {
+        assert!(read_pgm(b"P2\n1 1\n255\n0").is_err());
+        assert!(read_pgm(b"P5\n2 2\n255\n\x00").is_err());
+        assert!(read_pgm(b"P5\n1 1\n0\n\x00").is_err());
+        assert!(read_pgm(b"P5\n99999999999 99999999999\n255\n").is_err());
+    }
+
+    #[test]
+    fn fit_to_sensor_crops_the_centre() {
+        // Twice as wide as the sensor's aspect ratio: black borders, white centre.
+        let (width, height) = (2 * CAMERA_WIDTH * 2, CAMERA_HEIGHT * 2);
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| if (width / 4..3 * width / 4).contains(&(i % width)) { 0xFF } else { 0x00 })
+            .collect();
+        let image = fit_to_sensor(width, height, &pixels);
+        assert_eq!(image.len(), CAMERA_WIDTH * CAMERA_HEIGHT);
+        assert!(image.iter().all(|pixel| *pixel == 0xFF));
+    }
+}
@@ -1,103 +1,85 @@
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use sdl2::event::EventPollIterator;
-use sdl2::EventPump;
+use crate::constants::JOYPAD_POLL_TICKS;
+use crate::frontend::InputSource;
 use crate::memory::AddressSpace;
 use crate::interrupt::Interrupt;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+/// Joypad buttons, numbered by their bit in the joypad state byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
 
 pub struct Joypad {
     state: u8,
-    device_state: DeviceState,
+    polled_state: u8,
     ticks: u64,
-    event_pump: EventPump,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Joypad {
-    pub fn new(event_pump: EventPump) -> Joypad {
-        let device_state = DeviceState::new();
+    pub fn new() -> Joypad {
         Joypad {
             state: 0xFF,
-            device_state,
+            polled_state: 0xFF,
             ticks: 0,
-            event_pump
         }
     }
 
-    fn update_memory(&self, memory: &mut AddressSpace) {
-        memory.joypad_write(self.state); 
-    }
-
-    fn update_state(&mut self, memory: &mut AddressSpace) {
-        let keys: Vec<Keycode> = self.device_state.get_keys();
-        let prev_state = self.state;
-        if keys.contains(&Keycode::Right) || keys.contains(&Keycode::D) {
-            self.state &= 1 ^ 0xFF;
-        } else {
-            self.state |= 1;
-        }
-
-        if keys.contains(&Keycode::Left) || keys.contains(&Keycode::A) {
-            self.state &= (1 << 1) ^ 0xFF;
-        } else {
-            self.state |= 1 << 1;
-        }
-
-        if keys.contains(&Keycode::Up) || keys.contains(&Keycode::W) {
-            self.state &= (1 << 2) ^ 0xFF;
-        } else {
-            self.state |= 1 << 2;
-        }
-
-        if keys.contains(&Keycode::Down) || keys.contains(&Keycode::S) {
-            self.state &= (1 << 3) ^ 0xFF;
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.state &= (1 << button as u8) ^ 0xFF;
         } else {
-            self.state |= 1 << 3;
-        }
-
-        if keys.contains(&Keycode::Enter) {
-            self.state &= (1 << 4) ^ 0xFF;
-        } else {
-            self.state |= 1 << 4;
+            self.state |= 1 << button as u8;
         }
+    }
 
-        if keys.contains(&Keycode::Backspace) || keys.contains(&Keycode::Q) {
-            self.state &= (1 << 5) ^ 0xFF;
-        } else {
-            self.state |= 1 << 5;
-        }
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.state);
+        state.write_u8(self.polled_state);
+        state.write_u64(self.ticks);
+    }
 
-        if keys.contains(&Keycode::E) {
-            self.state &= (1 << 6) ^ 0xFF;
-        } else {
-            self.state |= 1 << 6;
-        }
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.state = state.read_u8()?;
+        self.polled_state = state.read_u8()?;
+        self.ticks = state.read_u64()?;
+        Ok(())
+    }
 
-        if keys.contains(&Keycode::Space) {
-            self.state &= (1 << 7) ^ 0xFF;
-        } else {
-            self.state |= 1 << 7;
-        }
+    fn update_memory(&self, memory: &mut AddressSpace) {
+        memory.joypad_write(self.state);
+    }
 
-        if prev_state == 0xFF && self.state != 0xFF {
+    fn update_state(&mut self, memory: &mut AddressSpace) {
+        if self.polled_state == 0xFF && self.state != 0xFF {
             memory.request_interrupt(Interrupt::Joypad);
         }
+        self.polled_state = self.state;
     }
 
-    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace) -> bool {
-        // let x_ = self.event_pump.poll_iter();
+    /// Returns true when the input source asked to quit.
+    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace, input: &mut dyn InputSource) -> bool {
         self.ticks += nticks as u64;
-        if self.ticks >= 7022 {
-            for event in self.event_pump.poll_iter() {
-                match event {
-                    _ => {}
-                }
-            }
-            self.ticks = self.ticks % 7022;
+        let mut quit = false;
+        if self.ticks >= JOYPAD_POLL_TICKS {
+            self.ticks %= JOYPAD_POLL_TICKS;
+            quit = input.poll(self);
             self.update_state(memory);
             self.update_memory(memory);
         }
-        if self.ticks % 1e6 as u64 == 0 && self.device_state.get_keys().contains(&Keycode::Escape) {
-            return true;
-        }
-        return false;
+        quit
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,24 @@
+#![allow(non_snake_case)]
+pub mod registers;
+pub mod cpu;
+pub mod memory;
+pub mod opcodes;
+pub mod constants;
+pub mod graphics;
+pub mod gameboy;
+pub mod interrupt;
+pub mod joypad;
+mod sprites;
+pub mod mappers;
+pub mod cartridge;
+pub mod sound;
+pub mod frontend;
+pub mod image_files;
+pub mod savestate;
+pub mod rewind;
+#[cfg(feature = "sdl")]
+pub mod sdl_frontend;
+
+pub use cartridge::LoadError;
+pub use gameboy::{Gameboy, Model, Speed};
+pub use joypad::Button;
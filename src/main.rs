@@ -1,22 +1,197 @@
-#![allow(non_snake_case)]
-mod registers;
-mod cpu;
-mod memory;
-mod opcodes;
-mod constants;
-mod graphics;
-mod gameboy;
-mod interrupt;
-mod joypad;
-mod sprites;
-mod mappers;
-mod sound;
+mod cli;
+#[cfg(feature = "sdl")]
+mod hotkeys;
 
 use std::path::Path;
-// 
-fn main() {
-    let path = Path::new("/Game/Path/*.gb");
-    let mut gb = gameboy::Gameboy::new();
-    gb.load_game(&path);
+use std::process::ExitCode;
+
+use rusting_empty::cartridge::CartridgeHeader;
+use rusting_empty::Gameboy;
+
+use cli::{Command, RunOptions, TestOptions, USAGE};
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("failed to read '{}': {e}", path.display()))
+}
+
+fn load_rom(gb: &mut Gameboy, path: &Path) -> Result<(), String> {
+    let game_bytes = read_rom(path)?;
+    let warnings = gb.load_rom(game_bytes).map_err(|e| format!("failed to load '{}': {e}", path.display()))?;
+    for warning in warnings {
+        eprintln!("warning: '{}': {warning}, loading anyway", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sdl")]
+fn run(options: RunOptions) -> Result<ExitCode, String> {
+    use rusting_empty::frontend::NullAudio;
+    use rusting_empty::image_files::ImageFiles;
+    use rusting_empty::sdl_frontend;
+    use hotkeys::{Hotkey, Hotkeys};
+
+    // Create the save directory up front, so a bad one is reported before the
+    // game runs rather than when its save is written at exit.
+    if let Some(dir) = &options.save_dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!("failed to create the save directory '{}': {e}", dir.display()))?;
+    }
+    let mut gb = Gameboy::new();
+    gb.set_model(options.model);
+    gb.set_mapper_override(options.mapper);
+    if let Some(dir) = &options.save_dir {
+        gb.set_save_dir(dir);
+    }
+    load_rom(&mut gb, &options.rom)?;
+    gb.set_rewind_budget(options.rewind_budget);
+    if let Some(path) = &options.camera {
+        let images = ImageFiles::open(path).map_err(|e| format!("failed to read camera images from '{}': {e}", path.display()))?;
+        gb.set_image_source(Box::new(images));
+    }
+
+    let (video, audio, input) = sdl_frontend::init(options.scale)
+        .map_err(|e| format!("failed to initialize SDL: {e}"))?;
+    gb.set_video_sink(Box::new(video));
+    if options.mute {
+        gb.set_audio_sink(Box::new(NullAudio));
+    } else {
+        gb.set_audio_sink(Box::new(audio));
+    }
+    gb.set_input_source(Box::new(input));
+
     gb.power_on();
-}
\ No newline at end of file
+    let mut hotkeys = Hotkeys::new();
+    let mut slot = 0;
+    let mut speed = options.speed;
+    gb.set_speed(speed);
+    while !gb.quit_requested() {
+        gb.run_frame();
+        for hotkey in hotkeys.poll() {
+            match hotkey {
+                Hotkey::SelectSlot(selected) => {
+                    slot = selected;
+                    println!("Selected save state slot {slot}");
+                },
+                Hotkey::SaveState => match gb.save_state_slot(slot) {
+                    Ok(()) => println!("Saved state to slot {slot}"),
+                    Err(e) => println!("Failed to save state to slot {slot}: {e}"),
+                },
+                Hotkey::LoadState => match gb.load_state_slot(slot) {
+                    Ok(()) => println!("Loaded state from slot {slot}"),
+                    Err(e) => println!("Failed to load state from slot {slot}: {e}"),
+                },
+                Hotkey::Slower | Hotkey::Faster => {
+                    speed = if matches!(hotkey, Hotkey::Slower) { speed.slower() } else { speed.faster() };
+                    println!("Speed: {speed}");
+                },
+            }
+        }
+        let (tilt_x, tilt_y) = hotkeys.tilt();
+        gb.set_tilt(tilt_x, tilt_y);
+        gb.set_rewinding(hotkeys.rewind_held());
+        gb.set_speed(if hotkeys.fast_forward_held() { options.fast_forward } else { speed });
+    }
+    gb.quit().map_err(|e| format!("failed to write the battery save: {e}"))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_options: RunOptions) -> Result<ExitCode, String> {
+    Err("this build has no SDL frontend, rebuild with `--features sdl` to play games".to_string())
+}
+
+fn valid(ok: bool) -> &'static str {
+    if ok { "ok" } else { "MISMATCH" }
+}
+
+fn info(rom: &Path) -> Result<ExitCode, String> {
+    let game_bytes = read_rom(rom)?;
+    let header = CartridgeHeader::parse_rom(&game_bytes).map_err(|e| format!("failed to read '{}': {e}", rom.display()))?;
+
+    println!("Title:            {}", header.title);
+    if let Some(code) = &header.manufacturer_code {
+        println!("Manufacturer:     {code}");
+    }
+    let licensee_code = if header.uses_new_licensee() {
+        format!("new \"{}\"", header.new_licensee)
+    } else {
+        format!("old ${:02X}", header.old_licensee)
+    };
+    println!("Licensee:         {} ({licensee_code})", header.licensee().unwrap_or("unknown"));
+    let cartridge = match header.cartridge() {
+        Ok(cartridge) => cartridge.to_string(),
+        Err(_) => "unknown".to_string(),
+    };
+    println!("Cartridge type:   ${:02X} {cartridge}", header.cartridge_type);
+    match header.rom_banks() {
+        Ok(banks) => println!("ROM size:         {} KiB, {banks} banks (file is {} KiB)", banks * 16, game_bytes.len() / 1024),
+        Err(e) => println!("ROM size:         {e}"),
+    }
+    match header.ram_banks() {
+        Ok(banks) => println!("RAM size:         {} KiB, {banks} banks", banks * 8),
+        Err(e) => println!("RAM size:         {e}"),
+    }
+    println!("CGB support:      {:?}", header.cgb_support);
+    println!("SGB support:      {}", if header.uses_sgb_functions() { "yes" } else { "no" });
+    println!("Destination:      {}", if header.japanese { "Japan" } else { "Overseas" });
+    println!("Version:          {}", header.version);
+    println!("Nintendo logo:    {}", valid(header.logo_valid));
+    println!("Header checksum:  ${:02X} {} (computed ${:02X})", header.header_checksum, valid(header.header_checksum_valid()), header.computed_header_checksum);
+    println!("Global checksum:  ${:04X} {} (computed ${:04X})", header.global_checksum, valid(header.global_checksum_valid()), header.computed_global_checksum);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs a test ROM headless until it reports a result over the serial port.
+fn test(options: TestOptions) -> Result<ExitCode, String> {
+    let mut gb = Gameboy::new();
+    gb.set_model(options.model);
+    gb.set_mapper_override(options.mapper);
+    load_rom(&mut gb, &options.rom)?;
+    gb.power_on();
+
+    let mut passed = false;
+    let mut failed = false;
+    for _ in 0..options.frames {
+        gb.run_frame();
+        let output = String::from_utf8_lossy(gb.serial_output());
+        passed = output.contains("Passed");
+        failed = output.contains("Failed");
+        if passed || failed {
+            break;
+        }
+    }
+    println!("{}", String::from_utf8_lossy(gb.serial_output()));
+    if passed {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        if !failed {
+            println!("No result after {} frames", options.frames);
+        }
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn main() -> ExitCode {
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        },
+    };
+    let result = match command {
+        Command::Run(options) => run(options),
+        Command::Info { rom } => info(&rom),
+        Command::Test(options) => test(options),
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        },
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -1,39 +1,264 @@
 #![allow(non_camel_case_types)]
 
+use crate::cartridge::{CartridgeHeader, LoadError, NINTENDO_LOGO};
 use crate::constants::*;
+use crate::frontend::ImageSource;
+use crate::savestate::{StateError, StateReader, StateWriter};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(PartialEq, Debug)]
-pub enum Cartridge {
-    RomOnly = 0x00,
-    MBC1 = 0x01,
-    MBC1_RAM_BATTERY = 0x03,
-    MBC2 = 0x05,
-    MBC3 = 0x11,
-    MBC3_RAM_BATTERY = 0x13,
-    MBC5 = 0x19,
-    MBC6 = 0x20,
+mod camera;
+mod huc1;
+mod huc3;
+mod mbc2;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod tama5;
+mod unlicensed;
+
+pub use camera::PocketCamera;
+pub use huc1::HuC1;
+pub use huc3::HuC3;
+pub use mbc2::MBC2;
+pub use mbc5::MBC5;
+pub use mbc6::MBC6;
+pub use mbc7::MBC7;
+pub use mmm01::MMM01;
+pub use tama5::TAMA5;
+pub use unlicensed::{detect_unlicensed, sachen_header, Sachen, WisdomTree, M161};
+
+/// The chip decoding the cartridge's address lines, from the cartridge type byte.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapperKind {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+    WisdomTree,
+    SachenMMC1,
+    SachenMMC2,
+    M161,
+}
+
+impl MapperKind {
+    /// Mappers no cartridge type byte stands for, only found by `detect_unlicensed`
+    /// or a user override.
+    pub fn is_unlicensed(self) -> bool {
+        matches!(self, MapperKind::WisdomTree | MapperKind::SachenMMC1 | MapperKind::SachenMMC2 | MapperKind::M161)
+    }
+}
+
+impl std::str::FromStr for MapperKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rom" => Ok(MapperKind::RomOnly),
+            "mbc1" => Ok(MapperKind::MBC1),
+            "mbc2" => Ok(MapperKind::MBC2),
+            "mmm01" => Ok(MapperKind::MMM01),
+            "mbc3" => Ok(MapperKind::MBC3),
+            "mbc5" => Ok(MapperKind::MBC5),
+            "mbc6" => Ok(MapperKind::MBC6),
+            "mbc7" => Ok(MapperKind::MBC7),
+            "camera" => Ok(MapperKind::PocketCamera),
+            "tama5" => Ok(MapperKind::TAMA5),
+            "huc3" => Ok(MapperKind::HuC3),
+            "huc1" => Ok(MapperKind::HuC1),
+            "wisdom-tree" => Ok(MapperKind::WisdomTree),
+            "sachen-mmc1" => Ok(MapperKind::SachenMMC1),
+            "sachen-mmc2" => Ok(MapperKind::SachenMMC2),
+            "m161" => Ok(MapperKind::M161),
+            _ => Err(format!("unknown mapper '{s}' (expected rom, mbc1, mbc2, mbc3, mbc5, mbc6, mbc7, mmm01, huc1, huc3, tama5, camera, wisdom-tree, sachen-mmc1, sachen-mmc2 or m161)")),
+        }
+    }
+}
+
+/// A cartridge type byte, split into the mapper and the extra hardware on the board.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cartridge {
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
+}
+
+impl Cartridge {
+    pub const fn new(mapper: MapperKind) -> Cartridge {
+        Cartridge {
+            mapper,
+            ram: false,
+            battery: false,
+            timer: false,
+            rumble: false,
+            sensor: false,
+        }
+    }
+
+    pub const fn with_ram(self) -> Cartridge {
+        Cartridge { ram: true, ..self }
+    }
+
+    pub const fn with_battery(self) -> Cartridge {
+        Cartridge { battery: true, ..self }
+    }
+
+    pub const fn with_timer(self) -> Cartridge {
+        Cartridge { timer: true, ..self }
+    }
+
+    pub const fn with_rumble(self) -> Cartridge {
+        Cartridge { rumble: true, ..self }
+    }
+
+    pub const fn with_sensor(self) -> Cartridge {
+        Cartridge { sensor: true, ..self }
+    }
+}
+
+impl std::fmt::Display for Cartridge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.mapper)?;
+        let flags = [
+            (self.timer, "TIMER"),
+            (self.sensor, "SENSOR"),
+            (self.rumble, "RUMBLE"),
+            (self.ram, "RAM"),
+            (self.battery, "BATTERY"),
+        ];
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, "+{name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Host UNIX time in seconds, stamped into saves of cartridges with a clock.
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The first `num_rom_banks` banks of the ROM, which must all be present.
+fn rom_banks(game_bytes: &[u8], num_rom_banks: usize) -> Result<Vec<u8>, LoadError> {
+    let rom_size = num_rom_banks * GB_ROM_BANK_SIZE;
+    if game_bytes.len() < rom_size {
+        return Err(LoadError::Truncated { len: game_bytes.len(), expected: rom_size });
+    }
+    Ok(game_bytes[..rom_size].to_vec())
 }
 
 pub trait Addressable {
-    fn new(game_bytes: Vec<u8>) -> Self where Self: Sized;
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> where Self: Sized;
     fn read(&self, index: u16) -> u8;
     fn write(&mut self, index: u16, value: u8);
     fn save_persistent_state(&self) -> Vec<u8>;
     fn load_persistent_state(&mut self, state: Vec<u8>);
     fn cartridge_type(&self) -> Option<Cartridge>;
+    /// Whether `save_persistent_state` outlives power off, so it is written
+    /// to disk on quit and handed back to `load_persistent_state` on load.
+    fn has_battery(&self) -> bool {
+        self.cartridge_type().is_some_and(|cartridge| cartridge.battery)
+    }
+    /// Whether the cartridge keeps time, in which case its persistent state
+    /// carries the clock too.
+    fn has_rtc(&self) -> bool {
+        self.cartridge_type().is_some_and(|cartridge| cartridge.timer)
+    }
     fn tick(&mut self, nticks: u8);
+    /// Whether the rumble motor is currently driven.
+    fn rumble(&self) -> bool {
+        false
+    }
+    /// Feeds the host's tilt, in g along each axis, to cartridges with an accelerometer.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    /// Hands cartridges with a camera the images their sensor captures.
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+    /// Writes the bank registers and RAM, everything but the ROM, into a save state.
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
 }
 
 
+impl std::convert::TryFrom<u8> for Cartridge {
+    type Error = LoadError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use MapperKind::*;
+        let cartridge = match value {
+            0x00 => Cartridge::new(RomOnly),
+            0x01 => Cartridge::new(MBC1),
+            0x02 => Cartridge::new(MBC1).with_ram(),
+            0x03 => Cartridge::new(MBC1).with_ram().with_battery(),
+            0x05 => Cartridge::new(MBC2).with_ram(),
+            0x06 => Cartridge::new(MBC2).with_ram().with_battery(),
+            0x08 => Cartridge::new(RomOnly).with_ram(),
+            0x09 => Cartridge::new(RomOnly).with_ram().with_battery(),
+            0x0B => Cartridge::new(MMM01),
+            0x0C => Cartridge::new(MMM01).with_ram(),
+            0x0D => Cartridge::new(MMM01).with_ram().with_battery(),
+            0x0F => Cartridge::new(MBC3).with_timer().with_battery(),
+            0x10 => Cartridge::new(MBC3).with_timer().with_ram().with_battery(),
+            0x11 => Cartridge::new(MBC3),
+            0x12 => Cartridge::new(MBC3).with_ram(),
+            0x13 => Cartridge::new(MBC3).with_ram().with_battery(),
+            0x19 => Cartridge::new(MBC5),
+            0x1A => Cartridge::new(MBC5).with_ram(),
+            0x1B => Cartridge::new(MBC5).with_ram().with_battery(),
+            0x1C => Cartridge::new(MBC5).with_rumble(),
+            0x1D => Cartridge::new(MBC5).with_rumble().with_ram(),
+            0x1E => Cartridge::new(MBC5).with_rumble().with_ram().with_battery(),
+            0x20 => Cartridge::new(MBC6).with_ram().with_battery(),
+            0x22 => Cartridge::new(MBC7).with_sensor().with_rumble().with_ram().with_battery(),
+            0xFC => Cartridge::new(PocketCamera).with_ram().with_battery(),
+            0xFD => Cartridge::new(TAMA5).with_timer().with_battery(),
+            0xFE => Cartridge::new(HuC3).with_timer().with_ram().with_battery(),
+            0xFF => Cartridge::new(HuC1).with_ram().with_battery(),
+            _ => return Err(LoadError::UnknownCartridgeType(value)),
+        };
+        Ok(cartridge)
+    }
+}
+
+/// Builds the mapper for `cartridge`, the only place that knows every mapper.
+pub fn new_mapper(cartridge: Cartridge, game_bytes: Vec<u8>) -> Result<Box<dyn Addressable>, LoadError> {
+    Ok(match cartridge.mapper {
+        MapperKind::RomOnly => Box::new(RomOnly::new(game_bytes)?),
+        MapperKind::MBC1 => Box::new(MBC1::new(game_bytes)?),
+        MapperKind::MMM01 => Box::new(MMM01::new(game_bytes)?),
+        MapperKind::MBC2 => Box::new(MBC2::new(game_bytes)?),
+        MapperKind::MBC3 => Box::new(MBC3::new(game_bytes)?),
+        MapperKind::MBC5 => Box::new(MBC5::new(game_bytes)?),
+        MapperKind::MBC6 => Box::new(MBC6::new(game_bytes)?),
+        MapperKind::MBC7 => Box::new(MBC7::new(game_bytes)?),
+        MapperKind::PocketCamera => Box::new(PocketCamera::new(game_bytes)?),
+        MapperKind::TAMA5 => Box::new(TAMA5::new(game_bytes)?),
+        MapperKind::HuC1 => Box::new(HuC1::new(game_bytes)?),
+        MapperKind::HuC3 => Box::new(HuC3::new(game_bytes)?),
+        MapperKind::WisdomTree => Box::new(WisdomTree::new(game_bytes)?),
+        MapperKind::SachenMMC1 | MapperKind::SachenMMC2 => Box::new(Sachen::with_kind(game_bytes, cartridge.mapper)?),
+        MapperKind::M161 => Box::new(M161::new(game_bytes)?),
+    })
+}
 
 #[derive(Debug)]
 pub struct NoCartridge {
 }
 
 impl Addressable for NoCartridge {
-    fn new(game_bytes: Vec<u8>) -> Self {
-        NoCartridge {
-        }
+    fn new(_game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        Ok(NoCartridge {
+        })
     }
 
     fn read(&self, index: u16) -> u8 {
@@ -43,188 +268,235 @@ impl Addressable for NoCartridge {
         }
     }
 
-    fn write(&mut self, index: u16, value: u8) {}
+    fn write(&mut self, _index: u16, _value: u8) {}
 
     fn save_persistent_state(&self) -> Vec<u8> {
         vec![]
     }
 
-    fn load_persistent_state(&mut self, state: Vec<u8>) {}
+    fn load_persistent_state(&mut self, _state: Vec<u8>) {}
 
     fn cartridge_type(&self) -> Option<Cartridge> {
         None
     }
 
-    fn tick(&mut self, nticks: u8) {}
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
 }
 
 
 #[derive(Debug)]
 pub struct RomOnly {
     rom: Vec<u8>,
+    ram: Vec<u8>,
+    cartridge: Cartridge,
 }
 
 impl Addressable for RomOnly {
-    fn new(game_bytes: Vec<u8>) -> Self {
-        let cartridge_type: Cartridge = Cartridge::from(game_bytes[0x147]);
-        if cartridge_type != Cartridge::RomOnly {
-            panic!("RomOnly cartridge expected, found {cartridge_type:?}");
-        }
-        RomOnly {
-            rom: game_bytes[..2*GB_ROM_BANK_SIZE].to_vec(),
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::RomOnly {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
         }
+        // Boards with RAM map at most one 8 KiB bank, unbanked.
+        let ram_size = if cartridge_type.ram { header.ram_banks()?.min(1) * CARTRIDGE_RAM_SIZE } else { 0 };
+        Ok(RomOnly {
+            rom: rom_banks(&game_bytes, 2)?,
+            ram: vec![0; ram_size],
+            cartridge: cartridge_type,
+        })
     }
 
     fn read(&self, index: u16) -> u8 {
         match index {
             0..=0x7FFF => return self.rom[index as usize],
+            0xA000..=0xBFFF => *self.ram.get(index as usize - 0xA000).unwrap_or(&0xFF),
             _ => unreachable!("Invalid access to ROM only cartridge at index {index}"),
         }
     }
 
-    fn write(&mut self, index: u16, value: u8) {}
+    fn write(&mut self, index: u16, value: u8) {
+        if let 0xA000..=0xBFFF = index {
+            if let Some(byte) = self.ram.get_mut(index as usize - 0xA000) {
+                *byte = value;
+            }
+        }
+    }
 
     fn save_persistent_state(&self) -> Vec<u8> {
-        vec![]
+        self.ram.clone()
     }
 
-    fn load_persistent_state(&mut self, state: Vec<u8>) {}
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
+    }
 
     fn cartridge_type(&self) -> Option<Cartridge> {
-        Some(Cartridge::RomOnly)
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
     }
 
-    fn tick(&mut self, nticks: u8) {}
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)
+    }
 }
 
 
 #[derive(Debug)]
 pub struct MBC1 {
+    cartridge: Cartridge,
     rom: Vec<u8>,
     ram: Vec<u8>,
+    /// BANK1, the low 5 bits of the ROM bank in 4000-7FFF.
     rom_select_register: u8,
+    /// BANK2, a 2 bit register that drives the upper ROM bank bits or the RAM bank.
     ram_select_register: u8,
+    /// In mode 1 BANK2 also applies to 0000-3FFF and to RAM.
     bank_mode_register: u8,
     external_ram_enable: bool,
+    /// MBC1M multicarts wire BANK2 to ROM bank bits 4-5 and drop BANK1's bit 4.
+    multicart: bool,
     num_rom_banks: usize,
     num_ram_banks: usize,
 }
 
+/// MBC1M boards hold four 256 KiB games in a 1 MiB ROM. Each game starts with
+/// its own header, so a Nintendo logo past the first 256 KiB gives them away.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    const GAME_SIZE: usize = 256 * 1024;
+    rom.len() == 4 * GAME_SIZE && (1..4).any(|game| {
+        let logo = NINTENDO_LOGO_ADDR.start + game * GAME_SIZE..NINTENDO_LOGO_ADDR.end + game * GAME_SIZE;
+        rom[logo] == NINTENDO_LOGO
+    })
+}
+
+impl MBC1 {
+    /// Bits BANK2 contributes to ROM bank numbers.
+    fn upper_rom_bank_bits(&self) -> usize {
+        if self.multicart {
+            (self.ram_select_register as usize) << 4
+        } else {
+            (self.ram_select_register as usize) << 5
+        }
+    }
+
+    fn rom_bank_0(&self) -> usize {
+        if self.bank_mode_register == 0 {
+            0
+        } else {
+            self.upper_rom_bank_bits() % self.num_rom_banks
+        }
+    }
+
+    fn rom_bank_1(&self) -> usize {
+        let lower_bits = if self.multicart {
+            self.rom_select_register & 0x0F
+        } else {
+            self.rom_select_register
+        };
+        (self.upper_rom_bank_bits() | lower_bits as usize) % self.num_rom_banks
+    }
+
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if !self.external_ram_enable || self.num_ram_banks == 0 {
+            return None
+        }
+        let bank_number = if self.bank_mode_register == 0 {
+            0
+        } else {
+            self.ram_select_register as usize % self.num_ram_banks
+        };
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+}
+
 impl Addressable for MBC1 {
-    fn new(game_bytes: Vec<u8>) -> Self {
-        let cartridge_type: Cartridge = Cartridge::from(game_bytes[0x147]);
-        if cartridge_type != Cartridge::MBC1 && cartridge_type != Cartridge::MBC1_RAM_BATTERY {
-            panic!("MBC1 cartridge expected, found {cartridge_type:?}");
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC1 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
         }
 
-        let num_rom_banks = 2 << game_bytes[0x148];
+        let num_rom_banks = header.rom_banks()?;
         let rom_size = num_rom_banks * 16;
         println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
-        if rom_size > 1024 {
-            unimplemented!("Alternate RAM wiring!")
-        }
-
-        let ram_banks_code = game_bytes[0x149];
-        let num_ram_banks = match ram_banks_code {
-            0x00 => 0,
-            0x01 => panic!("Unused ram code found"),
-            0x02 => 1,
-            0x03 => 4,
-            0x04 => 16,
-            0x05 => 8,
-            _ => unreachable!("Invalid ram bank code found: {ram_banks_code}"),
-        };
+        if num_rom_banks > 128 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        let num_ram_banks = header.ram_banks()?.min(4);
         let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
         println!("Ram with {num_ram_banks} banks, total {ram_size} KB");
+
+        let rom = rom_banks(&game_bytes, num_rom_banks)?;
+        let multicart = is_mbc1_multicart(&rom);
+        if multicart {
+            println!("MBC1M multicart detected");
+        }
         
-        MBC1 {
-            rom: game_bytes[..num_rom_banks * GB_ROM_BANK_SIZE].to_vec(),
+        Ok(MBC1 {
+            cartridge: cartridge_type,
+            rom,
             ram: vec![0; ram_size],
             rom_select_register: 1,
             ram_select_register: 0,
             bank_mode_register: 0,
             external_ram_enable: false,
+            multicart,
             num_rom_banks,
             num_ram_banks,
-        }
+        })
     }
 
     fn read(&self, index: u16) -> u8 {
-        let value = match index {
-            0..=0x3FFF => {
-                let bank_number;
-                if self.num_rom_banks <= 32 || self.bank_mode_register == 0 {
-                    bank_number = 0;
-                } else {
-                    bank_number = (self.ram_select_register << 5) as usize;
-                }
-                let bank_offset = bank_number * 0x4000;
-                self.rom[bank_offset + index as usize]
-            },
-            0x4000..=0x7FFF => {
-                let bank_number;
-                if self.num_rom_banks <= 32 {
-                    bank_number = self.rom_select_register as usize;
-                } else {
-                    bank_number = (self.ram_select_register << 5) as usize | self.rom_select_register as usize;
-                }
-                let bank_offset = bank_number * 0x4000;
-                self.rom[bank_offset + index as usize - 0x4000]
-            },
-            0xA000..=0xBFFF => {
-                if !self.external_ram_enable {
-                    return 0xFF
-                }
-                let bank_number;
-                if self.bank_mode_register == 0 {
-                    bank_number = 0;
-                } else {
-                    bank_number = self.ram_select_register as usize;
-                }
-                let bank_offset = bank_number * 0x2000;
-                self.ram[bank_offset + index as usize - 0xA000]
+        match index {
+            0..=0x3FFF => self.rom[self.rom_bank_0() * GB_ROM_BANK_SIZE + index as usize],
+            0x4000..=0x7FFF => self.rom[self.rom_bank_1() * GB_ROM_BANK_SIZE + index as usize - 0x4000],
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
             },
             _ => unreachable!("Invalid access to MBC1 cartridge at index {index}"),
-        };
-        value
+        }
     }
 
     fn write(&mut self, index: u16, value: u8) {
         match index {
             0..=0x1FFF => {
-                if value & 0xF == 0xA {
-                    self.external_ram_enable = true;
-                } else {
-                    self.external_ram_enable = false;
-                }
+                self.external_ram_enable = value & 0xF == 0xA;
             },
             0x2000..=0x3FFF => {
-                if value & 0x1F == 0 {
+                // The zero check sees all 5 bits, so on smaller ROMs a bank
+                // number that masks down to 0 still selects bank 0.
+                self.rom_select_register = value & 0x1F;
+                if self.rom_select_register == 0 {
                     self.rom_select_register = 1;
-                } else {
-                    self.rom_select_register = value & std::cmp::min(0x1F, self.num_rom_banks - 1) as u8;
                 }
             }
             0x4000..=0x5FFF => {
-                if self.num_ram_banks > 1 || self.num_rom_banks > 32 {
-                    self.ram_select_register = value & 0x03;
-                }
+                self.ram_select_register = value & 0x03;
             }
             0x6000..=0x7FFF => {
                 self.bank_mode_register = value & 0x01;
             }
             0xA000..=0xBFFF => {
-                if !self.external_ram_enable || self.ram.len() == 0 {
-                    return
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
                 }
-                let bank_number;
-                if self.bank_mode_register == 0 {
-                    bank_number = 0;
-                } else {
-                    bank_number = self.ram_select_register as usize;
-                }
-                let bank_offset = bank_number * 0x2000;
-                self.ram[bank_offset + index as usize - 0xA000] = value
             }
             _ => unreachable!("Invalid access to MBC1 cartridge at index {index}"),
         };
@@ -235,17 +507,41 @@ impl Addressable for MBC1 {
     }
 
     fn load_persistent_state(&mut self, state: Vec<u8>) {
-        self.ram = state;
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
     }
     
     fn cartridge_type(&self) -> Option<Cartridge> {
-        Some(Cartridge::MBC1)
+        Some(self.cartridge)
     }
 
-    fn tick(&mut self, nticks: u8) {}
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_u8(self.bank_mode_register);
+        state.write_bool(self.external_ram_enable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.ram_select_register = state.read_u8()?;
+        self.bank_mode_register = state.read_u8()?;
+        self.external_ram_enable = state.read_bool()?;
+        Ok(())
+    }
 }
 
 
+/// Size of the RTC footer appended to MBC3 battery saves.
+const RTC_FOOTER_SIZE: usize = 48;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
 #[derive(Debug, Clone, Copy)]
 pub struct RTCreg {
     RTCS: u8,
@@ -265,229 +561,492 @@ impl RTCreg {
             RTCDH: 0,
         }
     }
+
+    fn halted(&self) -> bool {
+        (self.RTCDH >> 6) & 1 == 1
+    }
+
+    fn days(&self) -> u16 {
+        ((self.RTCDH as u16 & 1) << 8) | self.RTCDL as u16
+    }
+
+    /// Sets the 9-bit day counter. Overflowing it sets the sticky day carry bit.
+    fn set_days(&mut self, days: u64) {
+        if days > 0x1FF {
+            self.RTCDH |= 0x80;
+        }
+        self.RTCDL = days as u8;
+        self.RTCDH = (self.RTCDH & 0xFE) | ((days >> 8) as u8 & 1);
+    }
+
+    /// Each counter only carries into the next when it reaches its normal
+    /// limit. A value written out of range keeps counting up to the top of its
+    /// bit width and wraps to 0 without carrying.
+    fn tick_second(&mut self) {
+        self.RTCS = (self.RTCS + 1) & 0x3F;
+        if self.RTCS != 60 {
+            return;
+        }
+        self.RTCS = 0;
+        self.RTCM = (self.RTCM + 1) & 0x3F;
+        if self.RTCM != 60 {
+            return;
+        }
+        self.RTCM = 0;
+        self.RTCH = (self.RTCH + 1) & 0x1F;
+        if self.RTCH != 24 {
+            return;
+        }
+        self.RTCH = 0;
+        self.set_days(self.days() as u64 + 1);
+    }
+
+    fn in_range(&self) -> bool {
+        self.RTCS < 60 && self.RTCM < 60 && self.RTCH < 24
+    }
+
+    /// Runs the clock forward by `seconds` of host time.
+    fn advance(&mut self, mut seconds: u64) {
+        // Out of range values follow their own wrapping rules, so step through
+        // them one second at a time before carrying whole days.
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let time_of_day = self.RTCS as u64 + 60 * self.RTCM as u64 + 3600 * self.RTCH as u64 + seconds;
+        self.RTCS = (time_of_day % 60) as u8;
+        self.RTCM = (time_of_day / 60 % 60) as u8;
+        self.RTCH = (time_of_day / 3600 % 24) as u8;
+        let days = time_of_day / SECONDS_PER_DAY;
+        if days > 0 {
+            self.set_days(self.days() as u64 + days);
+        }
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x8 => self.RTCS,
+            0x9 => self.RTCM,
+            0xA => self.RTCH,
+            0xB => self.RTCDL,
+            0xC => self.RTCDH,
+            _ => 0xFF,
+        }
+    }
+
+    /// Stores the bits the register actually has.
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x8 => self.RTCS = value & 0x3F,
+            0x9 => self.RTCM = value & 0x3F,
+            0xA => self.RTCH = value & 0x1F,
+            0xB => self.RTCDL = value,
+            0xC => self.RTCDH = value & 0xC1,
+            _ => (),
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_slice(&[self.RTCS, self.RTCM, self.RTCH, self.RTCDL, self.RTCDH]);
+    }
+
+    /// Appends the registers as five little-endian u32s, as in the RTC save footer.
+    fn write_footer(&self, footer: &mut Vec<u8>) {
+        for value in [self.RTCS, self.RTCM, self.RTCH, self.RTCDL, self.RTCDH] {
+            footer.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+    }
+
+    fn read_footer(footer: &[u8]) -> RTCreg {
+        let reg = |i: usize| footer[4 * i];
+        RTCreg {
+            RTCS: reg(0) & 0x3F,
+            RTCM: reg(1) & 0x3F,
+            RTCH: reg(2) & 0x1F,
+            RTCDL: reg(3),
+            RTCDH: reg(4) & 0xC1,
+        }
+    }
+
+    fn load_state(state: &mut StateReader) -> Result<RTCreg, StateError> {
+        let mut regs = [0u8; 5];
+        state.read_slice(&mut regs)?;
+        Ok(RTCreg {
+            RTCS: regs[0] & 0x3F,
+            RTCM: regs[1] & 0x3F,
+            RTCH: regs[2] & 0x1F,
+            RTCDL: regs[3],
+            RTCDH: regs[4] & 0xC1,
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct MBC3 {
+    cartridge: Cartridge,
     rom: Vec<u8>,
     ram: Vec<u8>,
     rom_select_register: u8,
+    /// RAM bank 0-7, or RTC register 8-C.
     ram_select_register: u8,
-    bank_mode_register: u8,
     external_ram_enable: bool,
+    /// Last value written to 6000-7FFF, the clock is latched when it goes from 0 to 1.
     latch_clock: u8,
     rtc: RTCreg,
-    latched_rtc: Option<RTCreg>,
+    latched_rtc: RTCreg,
     ticks_since_last_second: u32,
-    rtc_halted: bool,
-    
+    /// MBC30 boards widen the ROM bank register to 8 bits and the RAM bank to 3.
+    mbc30: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl MBC3 {
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if self.num_ram_banks == 0 {
+            return None
+        }
+        let ram_bank_mask = if self.mbc30 { 0x07 } else { 0x03 };
+        let bank_number = (self.ram_select_register & ram_bank_mask) as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
 }
 
 impl Addressable for MBC3 {
-    fn new(game_bytes: Vec<u8>) -> Self {
-        let cartridge_type: Cartridge = Cartridge::from(game_bytes[0x147]);
-        if cartridge_type != Cartridge::MBC3 && cartridge_type != Cartridge::MBC3_RAM_BATTERY  {
-            panic!("MBC3 cartridge expected, found {cartridge_type:?}");
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC3 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
         }
 
-        let num_rom_banks = 2 << game_bytes[0x148];
+        let num_rom_banks = header.rom_banks()?;
         let rom_size = num_rom_banks * 16;
         println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
-        if rom_size > 1024 {
-            unimplemented!("Alternate RAM wiring!")
-        }
-
-        let ram_banks_code = game_bytes[0x149];
-        let num_ram_banks = match ram_banks_code {
-            0x00 => 0,
-            0x01 => panic!("Unused ram code found"),
-            0x02 => 1,
-            0x03 => 4,
-            0x04 => 16,
-            0x05 => 8,
-            _ => unreachable!("Invalid ram bank code found: {ram_banks_code}"),
-        };
+        if num_rom_banks > 256 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        let num_ram_banks = header.ram_banks()?.min(8);
         let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
         println!("Ram with {num_ram_banks} banks, total {ram_size} KB");
+
+        // Only MBC30 can address more than 2 MiB of ROM or 32 KiB of RAM.
+        let mbc30 = num_rom_banks > 128 || num_ram_banks > 4;
+        if mbc30 {
+            println!("MBC30 detected");
+        }
         
-        MBC3 {
-            rom: game_bytes[..num_rom_banks * GB_ROM_BANK_SIZE].to_vec(),
+        Ok(MBC3 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
             ram: vec![0; ram_size],
             rom_select_register: 1,
             ram_select_register: 0,
-            bank_mode_register: 0,
             external_ram_enable: false,
-            latch_clock: 255,
+            latch_clock: 0xFF,
             rtc: RTCreg::new(),
-            latched_rtc: None,
+            latched_rtc: RTCreg::new(),
             ticks_since_last_second: 0,
-            rtc_halted: false,
-        }
+            mbc30,
+            num_rom_banks,
+            num_ram_banks,
+        })
     }
 
     fn read(&self, index: u16) -> u8 {
-        let value = match index {
-            0..=0x3FFF => {
-                self.rom[index as usize]
-            },
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
             0x4000..=0x7FFF => {
-                let bank_number = self.rom_select_register as usize;
-                let bank_offset = bank_number * 0x4000;
-                // println!("0x4000-0x7FFF: bank_number {bank_number}, index: {index}");
-                self.rom[bank_offset + index as usize - 0x4000]
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
             },
             0xA000..=0xBFFF => {
                 if !self.external_ram_enable {
                     return 0xFF
                 }
-
                 match self.ram_select_register {
-                    0..=7 => {
-                        let bank_number = self.ram_select_register as usize;
-                        let bank_offset = bank_number * 0x2000;
-                        self.ram[bank_offset + index as usize - 0xA000]
-                    }
-                    0x8 => {
-                        self.latched_rtc.unwrap().RTCS
-                    }
-                    0x9 => {
-                        self.latched_rtc.unwrap().RTCH
-                    }
-                    0xA => {
-                        self.latched_rtc.unwrap().RTCM
-                    }
-                    0xB => {
-                        self.latched_rtc.unwrap().RTCDL
-                    }
-                    0xC => {
-                        self.latched_rtc.unwrap().RTCDH
-                    }
+                    0..=7 => match self.ram_offset(index) {
+                        Some(offset) => self.ram[offset],
+                        None => 0xFF,
+                    },
+                    0x8..=0xC if self.has_rtc() => self.latched_rtc.read(self.ram_select_register),
                     _ => 0xFF,
                 }
             },
             _ => unreachable!("Invalid access to MBC3 cartridge at index {index}"),
-        };
-        value
+        }
     }
 
     fn write(&mut self, index: u16, value: u8) {
         match index {
             0..=0x1FFF => {
-                if value & 0xF == 0xA {
-                    self.external_ram_enable = true;
-                } else {
-                    self.external_ram_enable = false;
-                }
+                self.external_ram_enable = value & 0xF == 0xA;
             },
             0x2000..=0x3FFF => {
-                if value == 0 {
+                self.rom_select_register = if self.mbc30 { value } else { value & 0x7F };
+                if self.rom_select_register == 0 {
                     self.rom_select_register = 1;
-                } else {
-                    self.rom_select_register = value & 0x7F;
                 }
             }
             0x4000..=0x5FFF => {
-                self.ram_select_register = value & 0x7F
+                self.ram_select_register = value & 0x0F;
             }
             0x6000..=0x7FFF => {
-                if self.latch_clock == 0 && value == 1 {
-                    self.latched_rtc = Some(self.rtc);
+                if self.latch_clock & 1 == 0 && value & 1 == 1 {
+                    self.latched_rtc = self.rtc;
                 }
                 self.latch_clock = value;
             }
             0xA000..=0xBFFF => {
-                if !self.external_ram_enable || self.ram.len() == 0 {
+                if !self.external_ram_enable {
                     return
                 }
-
                 match self.ram_select_register {
-                    0..=0x7 => {
-                        let bank_number;
-                        if self.bank_mode_register == 0 {
-                            bank_number = 0;
-                        } else {
-                            bank_number = self.ram_select_register as usize;
+                    0..=7 => {
+                        if let Some(offset) = self.ram_offset(index) {
+                            self.ram[offset] = value;
                         }
-                        let bank_offset = bank_number * 0x2000;
-                        self.ram[bank_offset + index as usize - 0xA000] = value
                     }
-                    0x8 => {
-                        self.rtc.RTCS = value & 0x3F;
-                        self.latched_rtc.unwrap().RTCS = value & 0x3F;
-                        self.ticks_since_last_second = 0;
-                    }
-                    0x9 => {
-                        self.rtc.RTCH = value & 0x3F;
-                        self.latched_rtc.unwrap().RTCH = value & 0x3F;
-                    }
-                    0xA => {
-                        self.rtc.RTCM = value & 0x1F;
-                        self.latched_rtc.unwrap().RTCM = value & 0x1F;
-                    }
-                    0xB => {
-                        self.rtc.RTCDL = value & 0xFF;
-                        self.latched_rtc.unwrap().RTCDL = value & 0xFF;
-                    }
-                    0xC => {
-                        self.rtc.RTCDH = value & 0xC1;
-                        self.latched_rtc.unwrap().RTCDH = value & 0xC1;
-                        if (self.rtc.RTCDH >> 6) & 1 == 1 {
-                            self.rtc_halted = true;
-                        } else {
-                            self.rtc_halted = false;
+                    0x8..=0xC if self.has_rtc() => {
+                        // Writes reach the counting registers. The latched copy
+                        // mirrors them so the new value reads back immediately.
+                        if self.ram_select_register == 0x8 {
+                            self.ticks_since_last_second = 0;
                         }
+                        self.rtc.write(self.ram_select_register, value);
+                        self.latched_rtc.write(self.ram_select_register, value);
                     }
-                    _ => unreachable!("Invalid value for self.ram_select_register={}", self.ram_select_register),
-                }                    
+                    _ => (),
+                }
             }
             _ => unreachable!("Invalid access to MBC3 cartridge at index {index}"),
         };
     }
 
+    /// Cartridge RAM followed, on boards with a timer, by the 48-byte RTC
+    /// footer other emulators use: the current and latched registers as
+    /// little-endian u32s and the host UNIX time of the save as a u64.
     fn save_persistent_state(&self) -> Vec<u8> {
-        self.ram.clone()
+        let mut state = self.ram.clone();
+        if self.has_rtc() {
+            self.rtc.write_footer(&mut state);
+            self.latched_rtc.write_footer(&mut state);
+            state.extend_from_slice(&unix_time().to_le_bytes());
+        }
+        state
     }
 
-    fn load_persistent_state(&mut self, state: Vec<u8>) {
+    /// Accepts saves with or without the RTC footer, including its older
+    /// 44-byte form with a 32-bit timestamp. The clock is caught up with the
+    /// host time that passed since the save was written.
+    fn load_persistent_state(&mut self, mut state: Vec<u8>) {
+        if state.len() < self.ram.len() {
+            return;
+        }
+        let footer = state.split_off(self.ram.len());
         self.ram = state;
+        if !self.has_rtc() || (footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE - 4) {
+            return;
+        }
+        self.rtc = RTCreg::read_footer(&footer[..20]);
+        self.latched_rtc = RTCreg::read_footer(&footer[20..40]);
+        let timestamp = if footer.len() == RTC_FOOTER_SIZE {
+            u64::from_le_bytes(footer[40..48].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64
+        };
+        if !self.rtc.halted() {
+            self.rtc.advance(unix_time().saturating_sub(timestamp));
+        }
     }
 
     fn cartridge_type(&self) -> Option<Cartridge> {
-        Some(Cartridge::MBC3)
+        Some(self.cartridge)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_bool(self.external_ram_enable);
+        state.write_u8(self.latch_clock);
+        self.rtc.save_state(state);
+        self.latched_rtc.save_state(state);
+        state.write_u32(self.ticks_since_last_second);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.ram_select_register = state.read_u8()?;
+        self.external_ram_enable = state.read_bool()?;
+        self.latch_clock = state.read_u8()?;
+        self.rtc = RTCreg::load_state(state)?;
+        self.latched_rtc = RTCreg::load_state(state)?;
+        self.ticks_since_last_second = state.read_u32()?;
+        if self.ticks_since_last_second >= CLOCK_FREQ_HZ {
+            return Err(StateError::Invalid("RTC sub-second counter"));
+        }
+        Ok(())
     }
 
     fn tick(&mut self, nticks: u8) {
-        if self.rtc_halted {
+        if !self.has_rtc() || self.rtc.halted() {
             return;
         }
         self.ticks_since_last_second += nticks as u32;
         if self.ticks_since_last_second >= CLOCK_FREQ_HZ {
             self.ticks_since_last_second -= CLOCK_FREQ_HZ;
-            self.rtc.RTCS += 1;
-
-            if self.rtc.RTCS == 60 {
-                self.rtc.RTCS = 0;
-                self.rtc.RTCM += 1;
-
-                if self.rtc.RTCM == 60 {
-                    self.rtc.RTCM = 0;
-                    self.rtc.RTCH += 1;
-
-                    if self.rtc.RTCH == 24 {
-                        self.rtc.RTCH = 0;
-                        let overflow;
-                        (self.rtc.RTCDL, overflow) = self.rtc.RTCDL.overflowing_add(1);
-                        
-                        if overflow {
-                            if self.rtc.RTCDH & 1 == 1 {
-                                self.rtc.RTCDH &= 0x80;
-                            } else {
-                                self.rtc.RTCDH |= 1;
-                            }
-                        }
-                    }
-                }
-            }
+            self.rtc.tick_second();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM of `rom_size_code` with the given cartridge type and RAM size
+    /// code, and every bank's number written at offset 0x200 of the bank as a
+    /// little-endian u16.
+    pub(super) fn test_rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+        let mut rom = vec![0; GB_ROM_BANK_SIZE << (rom_size_code + 1)];
+        for (bank, bytes) in rom.chunks_mut(GB_ROM_BANK_SIZE).enumerate() {
+            bytes[0x200..0x202].copy_from_slice(&(bank as u16).to_le_bytes());
         }
+        rom[NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        rom[CARTRIDGE_TYPE_ADDR] = cartridge_type;
+        rom[ROM_SIZE_ADDR] = rom_size_code;
+        rom[RAM_SIZE_ADDR] = ram_size_code;
+        rom
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn day_counter_overflow_sets_the_carry() {
+        let mut rtc = RTCreg::new();
+        rtc.set_days(0x1FF);
+        assert_eq!(rtc.days(), 0x1FF);
+        assert_eq!(rtc.read(0xC) & 0x80, 0);
+        rtc.set_days(0x200);
+        assert_eq!(rtc.days(), 0);
+        assert_eq!(rtc.read(0xC) & 0x80, 0x80);
+        // The carry stays set until the game clears it.
+        rtc.set_days(1);
+        assert_eq!(rtc.read(0xC) & 0x80, 0x80);
+        rtc.write(0xC, 0x00);
+        assert_eq!(rtc.read(0xC), 0);
+    }
+
+    #[test]
+    fn seconds_carry_into_days() {
+        let mut rtc = RTCreg::new();
+        rtc.write(0x8, 59);
+        rtc.write(0x9, 59);
+        rtc.write(0xA, 23);
+        rtc.set_days(0x1FF);
+        rtc.tick_second();
+        assert_eq!([rtc.read(0x8), rtc.read(0x9), rtc.read(0xA)], [0, 0, 0]);
+        assert_eq!(rtc.days(), 0);
+        assert_eq!(rtc.read(0xC) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn out_of_range_seconds_wrap_without_carrying() {
+        let mut rtc = RTCreg::new();
+        rtc.write(0x8, 63);
+        rtc.tick_second();
+        assert_eq!(rtc.read(0x8), 0);
+        assert_eq!(rtc.read(0x9), 0);
+    }
+
+    #[test]
+    fn advance_carries_whole_days() {
+        let mut rtc = RTCreg::new();
+        rtc.write(0xA, 23);
+        rtc.advance(3 * SECONDS_PER_DAY + 3600 + 61);
+        assert_eq!([rtc.read(0x8), rtc.read(0x9), rtc.read(0xA)], [1, 1, 0]);
+        assert_eq!(rtc.days(), 4);
+    }
+
+    #[test]
+    fn mbc3_clock_catches_up_with_the_time_since_the_save() {
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.ram[0x1234] = 0x56;
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x0A);
+        mbc.write(0xA000, 5);
+        let mut save = mbc.save_persistent_state();
+        assert_eq!(save.len(), 4 * CARTRIDGE_RAM_SIZE + RTC_FOOTER_SIZE);
+        let an_hour_ago = unix_time() - 3600;
+        save[4 * CARTRIDGE_RAM_SIZE + 40..].copy_from_slice(&an_hour_ago.to_le_bytes());
+
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.load_persistent_state(save.clone());
+        assert_eq!(mbc.ram[0x1234], 0x56);
+        assert_eq!([mbc.rtc.read(0x9), mbc.rtc.read(0xA)], [0, 6]);
+        assert_eq!(mbc.latched_rtc.read(0xA), 5);
+
+        // The older footer stores the time as a u32.
+        save.truncate(4 * CARTRIDGE_RAM_SIZE + 44);
+        save[4 * CARTRIDGE_RAM_SIZE + 40..].copy_from_slice(&(an_hour_ago as u32).to_le_bytes());
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.load_persistent_state(save);
+        assert_eq!(mbc.rtc.read(0xA), 6);
+    }
+
+    #[test]
+    fn halted_mbc3_clock_does_not_catch_up() {
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.rtc.write(0xC, 0x40);
+        let mut save = mbc.save_persistent_state();
+        save[4 * CARTRIDGE_RAM_SIZE + 40..].copy_from_slice(&0u64.to_le_bytes());
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.load_persistent_state(save);
+        assert_eq!([mbc.rtc.read(0x8), mbc.rtc.read(0xA), mbc.rtc.read(0xC)], [0, 0, 0x40]);
+    }
+
+    #[test]
+    fn mbc3_latches_the_clock_on_a_0_to_1_write() {
+        let mut mbc = MBC3::new(test_rom(0x10, 0x00, 0x03)).unwrap();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x08);
+        mbc.rtc.write(0x8, 5);
+        // The register powers up holding neither 0 nor 1, so a lone 1 does nothing.
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 0);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 5);
+        mbc.rtc.write(0x8, 9);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 5);
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0xA000), 5);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 9);
+    }
+
+    #[test]
+    fn mbc1_multicart_is_detected_by_a_second_logo() {
+        let mut rom = test_rom(0x01, 0x05, 0x00);
+        assert!(!is_mbc1_multicart(&rom));
+        let second_game = 256 * 1024;
+        rom[second_game..][NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        assert!(is_mbc1_multicart(&rom));
+        // Only 1 MiB boards are multicarts.
+        assert!(!is_mbc1_multicart(&rom[..2 * second_game]));
+    }
+
+    #[test]
+    fn mbc1_multicart_maps_bank2_to_bank_bits_4_and_5() {
+        let mut rom = test_rom(0x01, 0x05, 0x00);
+        rom[256 * 1024..][NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = MBC1::new(rom).unwrap();
+        mbc.write(0x6000, 0x01);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x12);
+        assert_eq!(mbc.read(0x0200), 0x10);
+        assert_eq!(mbc.read(0x4200), 0x12);
+    }
+}
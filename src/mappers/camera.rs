@@ -0,0 +1,326 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::frontend::{ImageSource, NullImage};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+/// Sensor registers at A000-A035, mirrored every 0x80 bytes.
+const CAMERA_REGISTERS: usize = 0x36;
+const CAPTURE_FLAGS_REGISTER: usize = 0x00;
+const GAIN_REGISTER: usize = 0x01;
+const EXPOSURE_HIGH_REGISTER: usize = 0x02;
+const EXPOSURE_LOW_REGISTER: usize = 0x03;
+const EDGE_REGISTER: usize = 0x04;
+/// Start of the 4x4 dither matrix, three thresholds per pixel.
+const DITHER_MATRIX_REGISTER: usize = 0x06;
+/// Where the captured tiles are written in RAM bank 0.
+const IMAGE_RAM_OFFSET: usize = 0x100;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// The Game Boy Camera's mapper. Bit 4 of the RAM bank register maps the
+/// M64282FP sensor's registers over A000-BFFF in place of RAM. Setting bit 0 of
+/// A000 starts a capture, and once the exposure completes the image is dithered
+/// into 2bpp tiles at A100-AEFF of RAM bank 0.
+pub struct PocketCamera {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Bank in 4000-7FFF, 6 bits wide.
+    rom_select_register: u8,
+    ram_select_register: u8,
+    ram_write_enable: bool,
+    registers: [u8; CAMERA_REGISTERS],
+    /// Ticks until the running capture completes, 0 when idle.
+    capture_ticks: u32,
+    image_source: Box<dyn ImageSource>,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl PocketCamera {
+    fn registers_mapped(&self) -> bool {
+        self.ram_select_register & 0x10 != 0
+    }
+
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if self.num_ram_banks == 0 {
+            return None
+        }
+        let bank_number = (self.ram_select_register & 0x0F) as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.registers[EXPOSURE_HIGH_REGISTER] as u32) << 8 | self.registers[EXPOSURE_LOW_REGISTER] as u32
+    }
+
+    /// Capture time in ticks, counted by the sensor in CPU cycles.
+    fn capture_duration(&self) -> u32 {
+        let skip_negative = if self.registers[GAIN_REGISTER] & 0x80 != 0 { 0 } else { 512 };
+        4 * (32446 + skip_negative + 16 * self.exposure())
+    }
+
+    /// The sensor reading for a pixel, after inversion, gain and exposure.
+    /// Pixels outside the image repeat the nearest edge.
+    fn sensor_value(&self, image: &[u8], x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+        let y = y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+        let mut value = image[y * CAMERA_WIDTH + x] as f32;
+        if self.registers[EDGE_REGISTER] & 0x08 != 0 {
+            value = 255.0 - value;
+        }
+        // An approximation of the sensor's gain curve across the 5-bit setting.
+        let gain = 0.88 + (self.registers[GAIN_REGISTER] & 0x1F) as f32 * 0.025;
+        value * gain * self.exposure() as f32 / 0x1000 as f32
+    }
+
+    fn finish_capture(&mut self) {
+        let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.image_source.capture(&mut image);
+
+        let edge_mode = (self.registers[GAIN_REGISTER] >> 5) & 0x03;
+        let edge_ratio = EDGE_RATIOS[(self.registers[EDGE_REGISTER] >> 4) as usize & 0x07];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (sx, sy) = (x as isize, y as isize);
+                let center = self.sensor_value(&image, sx, sy);
+                let mut value = center;
+                // Edge modes 1, 2 and 3 sharpen horizontally, vertically or both.
+                if edge_mode & 0x01 != 0 {
+                    let neighbours = self.sensor_value(&image, sx - 1, sy) + self.sensor_value(&image, sx + 1, sy);
+                    value += (2.0 * center - neighbours) * edge_ratio;
+                }
+                if edge_mode & 0x02 != 0 {
+                    let neighbours = self.sensor_value(&image, sx, sy - 1) + self.sensor_value(&image, sx, sy + 1);
+                    value += (2.0 * center - neighbours) * edge_ratio;
+                }
+
+                let matrix = DITHER_MATRIX_REGISTER + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let shade = thresholds.iter().take_while(|threshold| value >= **threshold as f32).count();
+                let color = 3 - shade as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let offset = IMAGE_RAM_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                for plane in 0..2 {
+                    let byte = &mut self.ram[offset + plane];
+                    *byte = (*byte & !(1 << bit)) | (((color >> plane) & 1) << bit);
+                }
+            }
+        }
+        self.registers[CAPTURE_FLAGS_REGISTER] &= !0x01;
+    }
+}
+
+impl Addressable for PocketCamera {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::PocketCamera {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 64 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        // The image is always written to bank 0, so there is at least one.
+        let num_ram_banks = header.ram_banks()?.clamp(1, 16);
+        let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(PocketCamera {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; ram_size],
+            rom_select_register: 1,
+            ram_select_register: 0,
+            ram_write_enable: false,
+            registers: [0; CAMERA_REGISTERS],
+            capture_ticks: 0,
+            image_source: Box::new(NullImage),
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            // Only the capture flags can be read back.
+            0xA000..=0xBFFF if self.registers_mapped() => match (index as usize - 0xA000) & 0x7F {
+                CAPTURE_FLAGS_REGISTER => self.registers[CAPTURE_FLAGS_REGISTER],
+                _ => 0x00,
+            },
+            // The sensor owns the RAM while capturing.
+            0xA000..=0xBFFF if self.capture_ticks > 0 => 0x00,
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => unreachable!("Invalid access to PocketCamera cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.ram_write_enable = value & 0x0F == 0x0A;
+            },
+            0x2000..=0x3FFF => {
+                self.rom_select_register = value & 0x3F;
+            },
+            0x4000..=0x5FFF => {
+                self.ram_select_register = value & 0x1F;
+            },
+            0x6000..=0x7FFF => {},
+            0xA000..=0xBFFF if self.registers_mapped() => {
+                let register = (index as usize - 0xA000) & 0x7F;
+                if register == CAPTURE_FLAGS_REGISTER {
+                    self.registers[register] = value & 0x07;
+                    self.capture_ticks = if value & 0x01 != 0 { self.capture_duration() } else { 0 };
+                } else if register < CAMERA_REGISTERS {
+                    self.registers[register] = value;
+                }
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_write_enable || self.capture_ticks > 0 {
+                    return;
+                }
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
+                }
+            },
+            _ => unreachable!("Invalid access to PocketCamera cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, nticks: u8) {
+        if self.capture_ticks == 0 {
+            return;
+        }
+        self.capture_ticks = self.capture_ticks.saturating_sub(nticks as u32);
+        if self.capture_ticks == 0 {
+            self.finish_capture();
+        }
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.image_source = source;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_bool(self.ram_write_enable);
+        state.write_slice(&self.registers);
+        state.write_u32(self.capture_ticks);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.ram_select_register = state.read_u8()?;
+        self.ram_write_enable = state.read_bool()?;
+        state.read_slice(&mut self.registers)?;
+        self.capture_ticks = state.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    /// Black on the left half of the sensor and white on the right.
+    struct HalfAndHalf;
+
+    impl ImageSource for HalfAndHalf {
+        fn capture(&mut self, image: &mut [u8]) {
+            for (i, pixel) in image.iter_mut().enumerate() {
+                *pixel = if i % CAMERA_WIDTH < CAMERA_WIDTH / 2 { 0x00 } else { 0xFF };
+            }
+        }
+    }
+
+    fn camera() -> PocketCamera {
+        PocketCamera::new(test_rom(0xFC, 0x05, 0x04)).unwrap()
+    }
+
+    #[test]
+    fn registers_mirror_every_0x80_bytes() {
+        let mut camera = camera();
+        camera.write(0x4000, 0x10);
+        camera.write(0xA082, 0x12);
+        camera.write(0xBF03, 0x34);
+        assert_eq!(camera.exposure(), 0x1234);
+        camera.write(0xA180, 0x06);
+        assert_eq!(camera.read(0xA000), 0x06);
+        assert_eq!(camera.read(0xBF80), 0x06);
+        // Only the capture flags read back.
+        assert_eq!(camera.read(0xA002), 0x00);
+        // Registers past A035 do not exist.
+        camera.write(0xA040, 0xFF);
+        assert!(camera.registers.iter().all(|register| *register != 0xFF));
+    }
+
+    #[test]
+    fn capture_writes_tiles_into_bank_0() {
+        let mut camera = camera();
+        camera.set_image_source(Box::new(HalfAndHalf));
+        camera.write(0x0000, 0x0A);
+        camera.write(0x4000, 0x10);
+        camera.write(0xA002, 0x10);
+        camera.write(0xA003, 0x00);
+        for pixel in 0..16 {
+            for (i, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                camera.write(0xA000 + (DITHER_MATRIX_REGISTER + pixel * 3 + i) as u16, threshold);
+            }
+        }
+        camera.write(0xA000, 0x01);
+        assert_eq!(camera.read(0xA000) & 0x01, 0x01);
+
+        // The RAM belongs to the sensor until the capture completes.
+        camera.write(0x4000, 0x00);
+        camera.write(0xA100, 0x55);
+        assert_eq!(camera.read(0xA100), 0x00);
+        while camera.capture_ticks > 0 {
+            camera.tick(255);
+        }
+        // Black is color 3 in both bitplanes, white is color 0.
+        let first_row = IMAGE_RAM_OFFSET as u16 + 0xA000;
+        assert_eq!([camera.read(first_row), camera.read(first_row + 1)], [0xFF, 0xFF]);
+        let right_half = first_row + (CAMERA_WIDTH / 2 / 8 * 16) as u16;
+        assert_eq!([camera.read(right_half), camera.read(right_half + 1)], [0x00, 0x00]);
+        let last_tile = first_row + ((CAMERA_WIDTH / 8) * (CAMERA_HEIGHT / 8) * 16 - 1) as u16;
+        assert_eq!(camera.read(last_tile), 0x00);
+
+        camera.write(0x4000, 0x10);
+        assert_eq!(camera.read(0xA000) & 0x01, 0x00);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+/// Value written to 0000-1FFF that maps the infrared port over A000-BFFF.
+const HUC1_IR_MODE: u8 = 0x0E;
+
+/// Hudson's HuC1. Banks like a simplified MBC1, with an infrared LED and
+/// receiver. There is no RAM enable: the register other MBCs use for it maps
+/// either RAM, always readable and writable, or the infrared port over A000-BFFF.
+#[derive(Debug)]
+pub struct HuC1 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Bank in 4000-7FFF, 6 bits wide.
+    rom_select_register: u8,
+    ram_select_register: u8,
+    ir_mode: bool,
+    ir_led_on: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl HuC1 {
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if self.num_ram_banks == 0 {
+            return None
+        }
+        let bank_number = self.ram_select_register as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+}
+
+impl Addressable for HuC1 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::HuC1 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 64 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        let num_ram_banks = header.ram_banks()?.min(4);
+        let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(HuC1 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; ram_size],
+            rom_select_register: 1,
+            ram_select_register: 0,
+            ir_mode: false,
+            ir_led_on: false,
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            // Bit 0 is set while the receiver sees light. There is never another
+            // device to talk to, so it stays dark.
+            0xA000..=0xBFFF if self.ir_mode => 0xC0,
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => unreachable!("Invalid access to HuC1 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.ir_mode = value == HUC1_IR_MODE;
+            },
+            0x2000..=0x3FFF => {
+                self.rom_select_register = (value & 0x3F).max(1);
+            },
+            0x4000..=0x5FFF => {
+                self.ram_select_register = value & 0x03;
+            },
+            0x6000..=0x7FFF => {},
+            0xA000..=0xBFFF if self.ir_mode => {
+                self.ir_led_on = value & 0x01 != 0;
+            },
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
+                }
+            },
+            _ => unreachable!("Invalid access to HuC1 cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_bool(self.ir_mode);
+        state.write_bool(self.ir_led_on);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.ram_select_register = state.read_u8()?;
+        self.ir_mode = state.read_bool()?;
+        self.ir_led_on = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    #[test]
+    fn ram_needs_no_enable() {
+        let mut mbc = HuC1::new(test_rom(0xFF, 0x02, 0x03)).unwrap();
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0x42);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0xA000), 0x00);
+    }
+
+    #[test]
+    fn ir_mode_maps_the_port_over_ram() {
+        let mut mbc = HuC1::new(test_rom(0xFF, 0x02, 0x03)).unwrap();
+        mbc.write(0xA000, 0x42);
+        mbc.write(0x0000, HUC1_IR_MODE);
+        assert_eq!(mbc.read(0xA000), 0xC0);
+        mbc.write(0xA000, 0x01);
+        assert!(mbc.ir_led_on);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn rom_bank_is_6_bits_and_0_maps_to_1() {
+        let mut mbc = HuC1::new(test_rom(0xFF, 0x05, 0x03)).unwrap();
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4200), 1);
+        mbc.write(0x2000, 0xFF);
+        assert_eq!(mbc.read(0x4200), 0x3F);
+    }
+}
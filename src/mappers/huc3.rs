@@ -0,0 +1,401 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, unix_time, Addressable, Cartridge, MapperKind};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const TICKS_PER_MINUTE: u32 = CLOCK_FREQ_HZ * 60;
+/// Unix timestamp, minutes, days, alarm minutes, alarm days and alarm enable.
+const HUC3_FOOTER_SIZE: usize = 17;
+
+/// What the A000-BFFF window is mapped to, selected through 0000-1FFF.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum HuC3Mode {
+    RamRead,
+    RamReadWrite,
+    RtcCommand,
+    RtcResponse,
+    RtcSemaphore,
+    Infrared,
+    Unmapped,
+}
+
+impl HuC3Mode {
+    fn from_register(value: u8) -> HuC3Mode {
+        match value & 0x0F {
+            0x0 => HuC3Mode::RamRead,
+            0xA => HuC3Mode::RamReadWrite,
+            0xB => HuC3Mode::RtcCommand,
+            0xC => HuC3Mode::RtcResponse,
+            0xD => HuC3Mode::RtcSemaphore,
+            0xE => HuC3Mode::Infrared,
+            _ => HuC3Mode::Unmapped,
+        }
+    }
+}
+
+/// Hudson's HuC3. Besides banking it has an infrared port and a small
+/// microcontroller holding a minute/day clock and a tone generator, driven by
+/// writing 4-bit commands and reading back 4-bit responses.
+///
+/// The controller has 256 nibbles of memory. The clock is copied in and out of
+/// 00-05, minutes then days, least significant nibble first. The alarm lives at
+/// 10-16 and is only kept so the save round trips.
+#[derive(Debug)]
+pub struct HuC3 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Bank in 4000-7FFF, 7 bits wide.
+    rom_select_register: u8,
+    ram_select_register: u8,
+    mode_register: u8,
+    rtc_memory: [u8; 256],
+    rtc_address: u8,
+    rtc_command: u8,
+    rtc_response: u8,
+    minutes: u16,
+    days: u16,
+    ticks_since_last_minute: u32,
+    /// Set by the play-tone command. Nothing is played, the speaker is not emulated.
+    tone_requested: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl HuC3 {
+    fn mode(&self) -> HuC3Mode {
+        HuC3Mode::from_register(self.mode_register)
+    }
+
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if self.num_ram_banks == 0 {
+            return None
+        }
+        let bank_number = self.ram_select_register as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+
+    /// Reads a 12-bit value stored across three nibbles of controller memory.
+    fn memory_value(&self, address: usize) -> u16 {
+        (0..3).fold(0, |value, i| value | ((self.rtc_memory[address + i] as u16 & 0x0F) << (4 * i)))
+    }
+
+    fn set_memory_value(&mut self, address: usize, value: u16) {
+        for i in 0..3 {
+            self.rtc_memory[address + i] = (value >> (4 * i)) as u8 & 0x0F;
+        }
+    }
+
+    fn advance_minutes(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+        self.days = ((self.days as u64 + total / MINUTES_PER_DAY as u64) & 0xFFF) as u16;
+    }
+
+    fn execute_command(&mut self, value: u8) {
+        self.rtc_command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        match self.rtc_command {
+            0x1 => {
+                self.rtc_response = self.rtc_memory[self.rtc_address as usize];
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            },
+            0x3 => {
+                self.rtc_memory[self.rtc_address as usize] = argument;
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            },
+            0x4 => self.rtc_address = (self.rtc_address & 0xF0) | argument,
+            0x5 => self.rtc_address = (self.rtc_address & 0x0F) | (argument << 4),
+            0x6 => match argument {
+                0x0 => {
+                    self.set_memory_value(0x00, self.minutes);
+                    self.set_memory_value(0x03, self.days);
+                },
+                0x1 => {
+                    self.minutes = self.memory_value(0x00) % MINUTES_PER_DAY;
+                    self.days = self.memory_value(0x03);
+                    self.ticks_since_last_minute = 0;
+                },
+                0x2 => self.rtc_response = 0x1,
+                0xE => self.tone_requested = true,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Addressable for HuC3 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::HuC3 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 128 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        let num_ram_banks = header.ram_banks()?.min(4);
+        let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(HuC3 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; ram_size],
+            rom_select_register: 1,
+            ram_select_register: 0,
+            mode_register: 0,
+            rtc_memory: [0; 256],
+            rtc_address: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+            minutes: 0,
+            days: 0,
+            ticks_since_last_minute: 0,
+            tone_requested: false,
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            0xA000..=0xBFFF => match self.mode() {
+                HuC3Mode::RamRead | HuC3Mode::RamReadWrite => match self.ram_offset(index) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xFF,
+                },
+                HuC3Mode::RtcCommand | HuC3Mode::RtcResponse => 0x80 | (self.rtc_command << 4) | self.rtc_response,
+                // Commands complete immediately, so the controller is always ready.
+                HuC3Mode::RtcSemaphore => 0xFF,
+                // No light is ever received.
+                HuC3Mode::Infrared => 0xC0,
+                HuC3Mode::Unmapped => 0xFF,
+            },
+            _ => unreachable!("Invalid access to HuC3 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.mode_register = value & 0x0F;
+            },
+            0x2000..=0x3FFF => {
+                self.rom_select_register = (value & 0x7F).max(1);
+            },
+            0x4000..=0x5FFF => {
+                self.ram_select_register = value & 0x03;
+            },
+            0x6000..=0x7FFF => {},
+            0xA000..=0xBFFF => match self.mode() {
+                HuC3Mode::RamReadWrite => {
+                    if let Some(offset) = self.ram_offset(index) {
+                        self.ram[offset] = value;
+                    }
+                },
+                HuC3Mode::RtcCommand => self.execute_command(value),
+                _ => (),
+            },
+            _ => unreachable!("Invalid access to HuC3 cartridge at index {index}"),
+        }
+    }
+
+    /// Cartridge RAM followed by a 17-byte clock footer: the host UNIX time of
+    /// the save as a u64, then the minutes, days, alarm minutes and alarm days
+    /// as little-endian u16s and the alarm enable byte.
+    fn save_persistent_state(&self) -> Vec<u8> {
+        let mut state = self.ram.clone();
+        state.extend_from_slice(&unix_time().to_le_bytes());
+        state.extend_from_slice(&self.minutes.to_le_bytes());
+        state.extend_from_slice(&self.days.to_le_bytes());
+        state.extend_from_slice(&self.memory_value(0x10).to_le_bytes());
+        state.extend_from_slice(&self.memory_value(0x13).to_le_bytes());
+        state.push(self.rtc_memory[0x16]);
+        state
+    }
+
+    /// Accepts saves with or without the clock footer. The clock is caught up
+    /// with the host time that passed since the save was written.
+    fn load_persistent_state(&mut self, mut state: Vec<u8>) {
+        if state.len() < self.ram.len() {
+            return;
+        }
+        let footer = state.split_off(self.ram.len());
+        self.ram = state;
+        if footer.len() != HUC3_FOOTER_SIZE {
+            return;
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([footer[offset], footer[offset + 1]]);
+        let timestamp = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        self.minutes = u16_at(8) % MINUTES_PER_DAY;
+        self.days = u16_at(10) & 0xFFF;
+        self.set_memory_value(0x10, u16_at(12));
+        self.set_memory_value(0x13, u16_at(14));
+        self.rtc_memory[0x16] = footer[16] & 0x0F;
+        self.advance_minutes(unix_time().saturating_sub(timestamp) / 60);
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, nticks: u8) {
+        self.ticks_since_last_minute += nticks as u32;
+        if self.ticks_since_last_minute >= TICKS_PER_MINUTE {
+            self.ticks_since_last_minute -= TICKS_PER_MINUTE;
+            self.advance_minutes(1);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_u8(self.mode_register);
+        state.write_slice(&self.rtc_memory);
+        state.write_u8(self.rtc_address);
+        state.write_u8(self.rtc_command);
+        state.write_u8(self.rtc_response);
+        state.write_u16(self.minutes);
+        state.write_u16(self.days);
+        state.write_u32(self.ticks_since_last_minute);
+        state.write_bool(self.tone_requested);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.ram_select_register = state.read_u8()?;
+        self.mode_register = state.read_u8()?;
+        state.read_slice(&mut self.rtc_memory)?;
+        self.rtc_address = state.read_u8()?;
+        self.rtc_command = state.read_u8()?;
+        self.rtc_response = state.read_u8()?;
+        self.minutes = state.read_u16()?;
+        self.days = state.read_u16()?;
+        self.ticks_since_last_minute = state.read_u32()?;
+        self.tone_requested = state.read_bool()?;
+        if self.minutes >= MINUTES_PER_DAY || self.ticks_since_last_minute >= TICKS_PER_MINUTE {
+            return Err(StateError::Invalid("HuC3 clock"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    fn huc3() -> HuC3 {
+        HuC3::new(test_rom(0xFE, 0x02, 0x03)).unwrap()
+    }
+
+    /// Runs a controller command and returns the response register.
+    fn command(mbc: &mut HuC3, value: u8) -> u8 {
+        mbc.write(0x0000, 0x0B);
+        mbc.write(0xA000, value);
+        mbc.write(0x0000, 0x0C);
+        mbc.read(0xA000)
+    }
+
+    fn set_address(mbc: &mut HuC3, address: u8) {
+        command(mbc, 0x40 | (address & 0x0F));
+        command(mbc, 0x50 | (address >> 4));
+    }
+
+    fn write_value(mbc: &mut HuC3, address: u8, value: u16) {
+        set_address(mbc, address);
+        for i in 0..3 {
+            command(mbc, 0x30 | ((value >> (4 * i)) as u8 & 0x0F));
+        }
+    }
+
+    fn read_value(mbc: &mut HuC3, address: u8) -> u16 {
+        set_address(mbc, address);
+        (0..3).fold(0, |value, i| value | ((command(mbc, 0x10) as u16 & 0x0F) << (4 * i)))
+    }
+
+    #[test]
+    fn responses_echo_the_command() {
+        let mut mbc = huc3();
+        write_value(&mut mbc, 0x20, 0xABC);
+        set_address(&mut mbc, 0x20);
+        assert_eq!(command(&mut mbc, 0x10), 0x80 | 0x10 | 0x0C);
+        assert_eq!(command(&mut mbc, 0x62), 0x80 | 0x60 | 0x01);
+        mbc.write(0x0000, 0x0D);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn clock_is_copied_through_controller_memory() {
+        let mut mbc = huc3();
+        write_value(&mut mbc, 0x00, MINUTES_PER_DAY - 1);
+        write_value(&mut mbc, 0x03, 0x045);
+        command(&mut mbc, 0x61);
+        assert_eq!((mbc.minutes, mbc.days), (MINUTES_PER_DAY - 1, 0x045));
+
+        for _ in 0..TICKS_PER_MINUTE / 240 {
+            mbc.tick(240);
+        }
+        command(&mut mbc, 0x60);
+        assert_eq!(read_value(&mut mbc, 0x00), 0);
+        assert_eq!(read_value(&mut mbc, 0x03), 0x046);
+    }
+
+    #[test]
+    fn ram_writes_need_read_write_mode() {
+        let mut mbc = huc3();
+        mbc.write(0x0000, 0x00);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0x00);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x42);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn footer_round_trips_and_catches_up() {
+        let mut mbc = huc3();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x42);
+        write_value(&mut mbc, 0x10, 0x123);
+        write_value(&mut mbc, 0x13, 0x456);
+        write_value(&mut mbc, 0x16, 0x001);
+        mbc.minutes = 100;
+        mbc.days = 7;
+        let mut save = mbc.save_persistent_state();
+        assert_eq!(save.len(), 4 * CARTRIDGE_RAM_SIZE + HUC3_FOOTER_SIZE);
+        let ten_minutes_ago = unix_time() - 600;
+        save[4 * CARTRIDGE_RAM_SIZE..][..8].copy_from_slice(&ten_minutes_ago.to_le_bytes());
+
+        let mut mbc = huc3();
+        mbc.load_persistent_state(save.clone());
+        assert_eq!(mbc.ram[0], 0x42);
+        assert_eq!((mbc.minutes, mbc.days), (110, 7));
+        assert_eq!(read_value(&mut mbc, 0x10), 0x123);
+        assert_eq!(read_value(&mut mbc, 0x13), 0x456);
+        assert_eq!(mbc.rtc_memory[0x16], 0x1);
+
+        // A save without the footer only restores RAM.
+        let mut mbc = huc3();
+        mbc.load_persistent_state(save[..4 * CARTRIDGE_RAM_SIZE].to_vec());
+        assert_eq!(mbc.ram[0], 0x42);
+        assert_eq!((mbc.minutes, mbc.days), (0, 0));
+    }
+}
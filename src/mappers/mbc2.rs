@@ -0,0 +1,188 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+const MBC2_RAM_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub struct MBC2 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    /// Only the low nibble of each byte exists on the chip.
+    ram: Vec<u8>,
+    rom_select_register: u8,
+    external_ram_enable: bool,
+    num_rom_banks: usize,
+}
+
+impl Addressable for MBC2 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC2 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 16 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        Ok(MBC2 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; MBC2_RAM_SIZE],
+            rom_select_register: 1,
+            external_ram_enable: false,
+            num_rom_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            0xA000..=0xBFFF => {
+                if !self.external_ram_enable {
+                    return 0xFF
+                }
+                // The 512 nibbles repeat through the whole window and the
+                // missing upper data lines float high.
+                self.ram[index as usize & (MBC2_RAM_SIZE - 1)] | 0xF0
+            },
+            _ => unreachable!("Invalid access to MBC2 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            // Address bit 8 selects between the RAM enable and ROM bank registers.
+            0..=0x3FFF => {
+                if index & 0x100 == 0 {
+                    self.external_ram_enable = value & 0xF == 0xA;
+                } else if value & 0xF == 0 {
+                    self.rom_select_register = 1;
+                } else {
+                    self.rom_select_register = value & 0xF;
+                }
+            },
+            0x4000..=0x7FFF => {},
+            0xA000..=0xBFFF => {
+                if self.external_ram_enable {
+                    self.ram[index as usize & (MBC2_RAM_SIZE - 1)] = value & 0xF;
+                }
+            },
+            _ => unreachable!("Invalid access to MBC2 cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == MBC2_RAM_SIZE {
+            self.ram = state.iter().map(|value| value & 0xF).collect();
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_select_register);
+        state.write_bool(self.external_ram_enable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u8()?;
+        self.external_ram_enable = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    fn mbc2() -> MBC2 {
+        MBC2::new(test_rom(0x06, 0x03, 0x00)).unwrap()
+    }
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut mbc = mbc2();
+        mbc.write(0x0000, 0x03);
+        assert_eq!(mbc.read(0x4200), 1);
+        mbc.write(0x0100, 0x03);
+        assert_eq!(mbc.read(0x4200), 3);
+        mbc.write(0x3F00, 0x0A);
+        assert_eq!(mbc.read(0x4200), 0x0A);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        mbc.write(0x3EFF, 0x0A);
+        assert_eq!(mbc.read(0x4200), 0x0A);
+        assert_eq!(mbc.read(0xA000), 0xF0);
+    }
+
+    #[test]
+    fn rom_bank_is_4_bits_and_0_maps_to_1() {
+        let mut mbc = mbc2();
+        mbc.write(0x2100, 0x00);
+        assert_eq!(mbc.read(0x4200), 1);
+        mbc.write(0x2100, 0x1F);
+        assert_eq!(mbc.read(0x4200), 0x0F);
+        mbc.write(0x2100, 0x10);
+        assert_eq!(mbc.read(0x4200), 1);
+        assert_eq!(mbc.read(0x0200), 0);
+    }
+
+    #[test]
+    fn ram_holds_nibbles_mirrored_through_the_window() {
+        let mut mbc = mbc2();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0xAB);
+        mbc.write(0xA1FF, 0x05);
+        assert_eq!(mbc.read(0xA000), 0xFB);
+        assert_eq!(mbc.read(0xA1FF), 0xF5);
+        for mirror in [0xA200, 0xB000, 0xBE00] {
+            assert_eq!(mbc.read(mirror), 0xFB);
+            assert_eq!(mbc.read(mirror + 0x1FF), 0xF5);
+        }
+        mbc.write(0xBE01, 0x07);
+        assert_eq!(mbc.read(0xA001), 0xF7);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn ram_persists() {
+        let mut mbc = mbc2();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA123, 0x09);
+        let save = mbc.save_persistent_state();
+        assert_eq!(save.len(), MBC2_RAM_SIZE);
+
+        let mut mbc = mbc2();
+        mbc.load_persistent_state(save.clone());
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read(0xA123), 0xF9);
+        // Saves of another size are ignored.
+        let mut mbc = mbc2();
+        mbc.load_persistent_state(save[..256].to_vec());
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read(0xA123), 0xF0);
+    }
+}
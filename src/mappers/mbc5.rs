@@ -0,0 +1,207 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+#[derive(Debug)]
+pub struct MBC5 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Bank in 4000-7FFF, 9 bits wide. Unlike earlier MBCs bank 0 can be selected.
+    rom_select_register: u16,
+    ram_select_register: u8,
+    external_ram_enable: bool,
+    rumble_on: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl MBC5 {
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if !self.external_ram_enable || self.num_ram_banks == 0 {
+            return None
+        }
+        let bank_number = self.ram_select_register as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+}
+
+impl Addressable for MBC5 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC5 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+
+        let num_ram_banks = header.ram_banks()?;
+        let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(MBC5 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; ram_size],
+            rom_select_register: 1,
+            ram_select_register: 0,
+            external_ram_enable: false,
+            rumble_on: false,
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => unreachable!("Invalid access to MBC5 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.external_ram_enable = value == 0x0A;
+            },
+            0x2000..=0x2FFF => {
+                self.rom_select_register = (self.rom_select_register & 0x100) | value as u16;
+            },
+            0x3000..=0x3FFF => {
+                self.rom_select_register = (self.rom_select_register & 0xFF) | ((value as u16 & 0x01) << 8);
+            },
+            0x4000..=0x5FFF => {
+                // On rumble boards bit 3 drives the motor instead of a RAM address line.
+                if self.cartridge.rumble {
+                    self.rumble_on = value & 0x08 != 0;
+                    self.ram_select_register = value & 0x07;
+                } else {
+                    self.ram_select_register = value & 0x0F;
+                }
+            },
+            0x6000..=0x7FFF => {},
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
+                }
+            },
+            _ => unreachable!("Invalid access to MBC5 cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn rumble(&self) -> bool {
+        self.rumble_on
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u16(self.rom_select_register);
+        state.write_u8(self.ram_select_register);
+        state.write_bool(self.external_ram_enable);
+        state.write_bool(self.rumble_on);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.rom_select_register = state.read_u16()?;
+        self.ram_select_register = state.read_u8()?;
+        self.external_ram_enable = state.read_bool()?;
+        self.rumble_on = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    fn rom_bank(mbc: &MBC5) -> u16 {
+        u16::from_le_bytes([mbc.read(0x4200), mbc.read(0x4201)])
+    }
+
+    #[test]
+    fn rom_bank_is_9_bits() {
+        let mut mbc = MBC5::new(test_rom(0x19, 0x08, 0x00)).unwrap();
+        mbc.write(0x2000, 0x23);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(rom_bank(&mbc), 0x123);
+        mbc.write(0x2FFF, 0x45);
+        assert_eq!(rom_bank(&mbc), 0x145);
+        mbc.write(0x3FFF, 0xFE);
+        assert_eq!(rom_bank(&mbc), 0x45);
+    }
+
+    #[test]
+    fn bank_0_can_be_selected_at_4000() {
+        let mut mbc = MBC5::new(test_rom(0x19, 0x02, 0x00)).unwrap();
+        assert_eq!(rom_bank(&mbc), 1);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(rom_bank(&mbc), 0);
+    }
+
+    #[test]
+    fn ram_bank_wraps_to_the_ram_size() {
+        let mut mbc = MBC5::new(test_rom(0x1B, 0x00, 0x03)).unwrap();
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..4 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, 0x10 + bank);
+        }
+        mbc.write(0x4000, 0x05);
+        assert_eq!(mbc.read(0xA000), 0x11);
+        mbc.write(0x4000, 0x0F);
+        assert_eq!(mbc.read(0xA000), 0x13);
+    }
+
+    #[test]
+    fn rumble_boards_use_bit_3_for_the_motor() {
+        let mut mbc = MBC5::new(test_rom(0x1E, 0x00, 0x04)).unwrap();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x55);
+        mbc.write(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read(0xA000), 0x55);
+        mbc.write(0x4000, 0x01);
+        assert!(!mbc.rumble());
+
+        // Without a motor bit 3 selects RAM banks 8-15.
+        let mut mbc = MBC5::new(test_rom(0x1B, 0x00, 0x04)).unwrap();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x55);
+        mbc.write(0x4000, 0x09);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read(0xA000), 0x00);
+    }
+}
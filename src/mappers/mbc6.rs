@@ -0,0 +1,464 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+const MBC6_ROM_BANK_SIZE: usize = 8 * 1024;
+const MBC6_RAM_BANK_SIZE: usize = 4 * 1024;
+const FLASH_SIZE: usize = 1024 * 1024;
+const FLASH_SECTOR_SIZE: usize = 128 * 1024;
+const FLASH_PAGE_SIZE: usize = 128;
+const FLASH_MANUFACTURER_ID: u8 = 0xC2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+/// Progress through the flash chip's unlock sequences.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashMode {
+    Read,
+    /// Got AA at 5555.
+    Unlock1,
+    /// Got AA, 55 at 2AAA.
+    Unlock2,
+    /// Reads return the manufacturer and device IDs.
+    Id,
+    /// Writes within the page being programmed clear bits.
+    Program,
+    /// Got the 80 erase prefix, waiting for a second unlock.
+    Erase,
+    Erase1,
+    Erase2,
+}
+
+impl FlashMode {
+    const ALL: [FlashMode; 8] = [
+        FlashMode::Read,
+        FlashMode::Unlock1,
+        FlashMode::Unlock2,
+        FlashMode::Id,
+        FlashMode::Program,
+        FlashMode::Erase,
+        FlashMode::Erase1,
+        FlashMode::Erase2,
+    ];
+}
+
+/// The MX29F008 1 MiB flash chip, commanded with the usual AA/55 unlock writes
+/// to 5555 and 2AAA. Programming and erasing complete immediately.
+#[derive(Debug)]
+struct Flash {
+    data: Vec<u8>,
+    mode: FlashMode,
+    /// The 128-byte page a program command is writing to, set by its first write.
+    program_page: Option<usize>,
+}
+
+impl Flash {
+    fn new() -> Flash {
+        Flash {
+            data: vec![0xFF; FLASH_SIZE],
+            mode: FlashMode::Read,
+            program_page: None,
+        }
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        match self.mode {
+            FlashMode::Id => match address & 0xFF {
+                0x00 => FLASH_MANUFACTURER_ID,
+                0x01 => FLASH_DEVICE_ID,
+                _ => 0x00,
+            },
+            _ => self.data[address % FLASH_SIZE],
+        }
+    }
+
+    /// A write reaching the chip. Without `write_enabled` commands are still
+    /// decoded, but programming and erasing leave the contents untouched.
+    fn write(&mut self, address: usize, value: u8, write_enabled: bool) {
+        let address = address % FLASH_SIZE;
+        let command_address = address & 0xFFFF;
+        // F0 resets the chip, unless it is data being programmed.
+        if value == 0xF0 && self.mode != FlashMode::Program {
+            self.mode = FlashMode::Read;
+            return;
+        }
+        self.mode = match (self.mode, command_address, value) {
+            (FlashMode::Read | FlashMode::Id, 0x5555, 0xAA) => FlashMode::Unlock1,
+            (FlashMode::Unlock1, 0x2AAA, 0x55) => FlashMode::Unlock2,
+            (FlashMode::Unlock2, 0x5555, 0x90) => FlashMode::Id,
+            (FlashMode::Unlock2, 0x5555, 0xA0) => {
+                self.program_page = None;
+                FlashMode::Program
+            },
+            (FlashMode::Unlock2, 0x5555, 0x80) => FlashMode::Erase,
+            (FlashMode::Program, _, _) => {
+                let page = address / FLASH_PAGE_SIZE;
+                if *self.program_page.get_or_insert(page) != page {
+                    FlashMode::Read
+                } else {
+                    if write_enabled {
+                        self.data[address] &= value;
+                    }
+                    FlashMode::Program
+                }
+            },
+            (FlashMode::Erase, 0x5555, 0xAA) => FlashMode::Erase1,
+            (FlashMode::Erase1, 0x2AAA, 0x55) => FlashMode::Erase2,
+            (FlashMode::Erase2, 0x5555, 0x10) => {
+                if write_enabled {
+                    self.data.fill(0xFF);
+                }
+                FlashMode::Read
+            },
+            (FlashMode::Erase2, _, 0x30) => {
+                if write_enabled {
+                    let sector = address / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                    self.data[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+                }
+                FlashMode::Read
+            },
+            // Anything out of sequence drops back to reading.
+            _ => FlashMode::Read,
+        };
+    }
+
+    /// The whole chip is written so that every state has the same length and
+    /// rewind deltas stay valid. Flash that did not change costs nothing in
+    /// the deltas, only the newest snapshot holds it in full.
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_slice(&self.data);
+        state.write_u8(FlashMode::ALL.iter().position(|mode| *mode == self.mode).unwrap() as u8);
+        state.write_bool(self.program_page.is_some());
+        state.write_u32(self.program_page.unwrap_or(0) as u32);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_slice(&mut self.data)?;
+        self.mode = *FlashMode::ALL.get(state.read_u8()? as usize).ok_or(StateError::Invalid("flash mode"))?;
+        let has_program_page = state.read_bool()?;
+        let program_page = state.read_u32()? as usize;
+        self.program_page = has_program_page.then_some(program_page);
+        Ok(())
+    }
+}
+
+/// The MBC6, used by Net de Get. 4000-7FFF is split into two 8 KiB windows,
+/// each showing a ROM or flash bank, and A000-BFFF into two 4 KiB RAM windows.
+#[derive(Debug)]
+pub struct MBC6 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Flash,
+    external_ram_enable: bool,
+    /// 8 KiB banks shown at 4000-5FFF and 6000-7FFF.
+    rom_select_registers: [u8; 2],
+    /// Whether each ROM window shows flash instead of ROM.
+    flash_selected: [bool; 2],
+    /// 4 KiB banks shown at A000-AFFF and B000-BFFF.
+    ram_select_registers: [u8; 2],
+    flash_enable: bool,
+    flash_write_enable: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl MBC6 {
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if !self.external_ram_enable || self.num_ram_banks == 0 {
+            return None
+        }
+        let window = (index as usize - 0xA000) / MBC6_RAM_BANK_SIZE;
+        let bank_number = self.ram_select_registers[window] as usize % self.num_ram_banks;
+        Some(bank_number * MBC6_RAM_BANK_SIZE + (index as usize & (MBC6_RAM_BANK_SIZE - 1)))
+    }
+
+    /// The flash address behind a ROM window access.
+    fn flash_address(&self, window: usize, index: u16) -> usize {
+        self.rom_select_registers[window] as usize * MBC6_ROM_BANK_SIZE + (index as usize & (MBC6_ROM_BANK_SIZE - 1))
+    }
+}
+
+impl Addressable for MBC6 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC6 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let rom_banks_16k = header.rom_banks()?;
+        let rom_size = rom_banks_16k * 16;
+        println!("Rom with {rom_banks_16k} banks, total {rom_size} KB");
+        let num_rom_banks = rom_banks_16k * GB_ROM_BANK_SIZE / MBC6_ROM_BANK_SIZE;
+
+        let ram_size = header.ram_banks()? * CARTRIDGE_RAM_SIZE;
+        let num_ram_banks = ram_size / MBC6_RAM_BANK_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(MBC6 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, rom_banks_16k)?,
+            ram: vec![0; ram_size],
+            flash: Flash::new(),
+            external_ram_enable: false,
+            rom_select_registers: [2, 3],
+            flash_selected: [false, false],
+            ram_select_registers: [0, 1],
+            flash_enable: false,
+            flash_write_enable: false,
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let window = (index as usize - 0x4000) / MBC6_ROM_BANK_SIZE;
+                if self.flash_selected[window] {
+                    self.flash.read(self.flash_address(window, index))
+                } else {
+                    let bank_number = self.rom_select_registers[window] as usize % self.num_rom_banks;
+                    self.rom[bank_number * MBC6_ROM_BANK_SIZE + (index as usize & (MBC6_ROM_BANK_SIZE - 1))]
+                }
+            },
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => unreachable!("Invalid access to MBC6 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0x0000..=0x03FF => self.external_ram_enable = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_select_registers[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_select_registers[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enable = value & 0x01 != 0,
+            0x1000..=0x1FFF => self.flash_write_enable = value & 0x01 != 0,
+            0x2000..=0x27FF => self.rom_select_registers[0] = value & 0x7F,
+            0x2800..=0x2FFF => self.flash_selected[0] = value == 0x08,
+            0x3000..=0x37FF => self.rom_select_registers[1] = value & 0x7F,
+            0x3800..=0x3FFF => self.flash_selected[1] = value == 0x08,
+            0x4000..=0x7FFF => {
+                let window = (index as usize - 0x4000) / MBC6_ROM_BANK_SIZE;
+                if self.flash_selected[window] && self.flash_enable {
+                    let address = self.flash_address(window, index);
+                    self.flash.write(address, value, self.flash_write_enable);
+                }
+            },
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
+                }
+            },
+            _ => unreachable!("Invalid access to MBC6 cartridge at index {index}"),
+        }
+    }
+
+    /// Cartridge RAM followed by the whole flash chip.
+    fn save_persistent_state(&self) -> Vec<u8> {
+        let mut state = self.ram.clone();
+        state.extend_from_slice(&self.flash.data);
+        state
+    }
+
+    /// Accepts saves with or without the flash contents.
+    fn load_persistent_state(&mut self, mut state: Vec<u8>) {
+        if state.len() != self.ram.len() && state.len() != self.ram.len() + FLASH_SIZE {
+            return;
+        }
+        let flash = state.split_off(self.ram.len());
+        self.ram = state;
+        if !flash.is_empty() {
+            self.flash.data = flash;
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        self.flash.save_state(state);
+        state.write_bool(self.external_ram_enable);
+        state.write_slice(&self.rom_select_registers);
+        state.write_bool(self.flash_selected[0]);
+        state.write_bool(self.flash_selected[1]);
+        state.write_slice(&self.ram_select_registers);
+        state.write_bool(self.flash_enable);
+        state.write_bool(self.flash_write_enable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.flash.load_state(state)?;
+        self.external_ram_enable = state.read_bool()?;
+        state.read_slice(&mut self.rom_select_registers)?;
+        self.flash_selected = [state.read_bool()?, state.read_bool()?];
+        state.read_slice(&mut self.ram_select_registers)?;
+        self.flash_enable = state.read_bool()?;
+        self.flash_write_enable = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    /// A 256 KiB MBC6 with 32 KiB of RAM, with every 8 KiB ROM bank's number at
+    /// offset 0x300 of the bank.
+    fn mbc6() -> MBC6 {
+        let mut rom = test_rom(0x20, 0x03, 0x03);
+        for (bank, bytes) in rom.chunks_mut(MBC6_ROM_BANK_SIZE).enumerate() {
+            bytes[0x300] = bank as u8;
+        }
+        MBC6::new(rom).unwrap()
+    }
+
+    /// Maps flash bank 2 at 4000, holding 5555, and bank 17 at 6000, where
+    /// 6AAA decodes as 2AAA and the rest lies in the second sector.
+    fn flash_mbc6() -> MBC6 {
+        let mut mbc = mbc6();
+        mbc.write(0x0C00, 0x01);
+        mbc.write(0x1000, 0x01);
+        mbc.write(0x2000, 2);
+        mbc.write(0x2800, 0x08);
+        mbc.write(0x3000, 17);
+        mbc.write(0x3800, 0x08);
+        mbc
+    }
+
+    fn unlock(mbc: &mut MBC6) {
+        mbc.write(0x5555, 0xAA);
+        mbc.write(0x6AAA, 0x55);
+    }
+
+    fn program(mbc: &mut MBC6, index: u16, value: u8) {
+        unlock(mbc);
+        mbc.write(0x5555, 0xA0);
+        mbc.write(index, value);
+        // A write outside the page ends programming.
+        mbc.write(0x5555, 0xF0);
+    }
+
+    fn erase(mbc: &mut MBC6, index: u16, command: u8) {
+        unlock(mbc);
+        mbc.write(0x5555, 0x80);
+        unlock(mbc);
+        mbc.write(index, command);
+    }
+
+    #[test]
+    fn rom_windows_bank_independently() {
+        let mut mbc = mbc6();
+        assert_eq!([mbc.read(0x4300), mbc.read(0x6300)], [2, 3]);
+        mbc.write(0x2000, 7);
+        mbc.write(0x3000, 30);
+        assert_eq!([mbc.read(0x4300), mbc.read(0x6300)], [7, 30]);
+        mbc.write(0x37FF, 0x80 | 5);
+        assert_eq!(mbc.read(0x6300), 5);
+        assert_eq!(mbc.read(0x0300), 0);
+    }
+
+    #[test]
+    fn ram_windows_bank_independently() {
+        let mut mbc = mbc6();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x0400, 2);
+        mbc.write(0x0800, 5);
+        mbc.write(0xA000, 0x11);
+        mbc.write(0xBFFF, 0x22);
+        mbc.write(0x0400, 5);
+        assert_eq!(mbc.read(0xA000), 0x00);
+        assert_eq!(mbc.read(0xAFFF), 0x22);
+        assert_eq!(mbc.ram[2 * MBC6_RAM_BANK_SIZE], 0x11);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xAFFF), 0xFF);
+    }
+
+    #[test]
+    fn program_clears_bits_once_unlocked() {
+        let mut mbc = flash_mbc6();
+        mbc.write(0x4000, 0x12);
+        assert_eq!(mbc.read(0x4000), 0xFF);
+        program(&mut mbc, 0x4000, 0x12);
+        assert_eq!(mbc.read(0x4000), 0x12);
+        program(&mut mbc, 0x4000, 0xF1);
+        assert_eq!(mbc.read(0x4000), 0x10);
+
+        mbc.write(0x1000, 0x00);
+        program(&mut mbc, 0x4001, 0x00);
+        assert_eq!(mbc.read(0x4001), 0xFF);
+    }
+
+    #[test]
+    fn id_mode_reports_the_chip() {
+        let mut mbc = flash_mbc6();
+        unlock(&mut mbc);
+        mbc.write(0x5555, 0x90);
+        assert_eq!([mbc.read(0x4000), mbc.read(0x4001)], [FLASH_MANUFACTURER_ID, FLASH_DEVICE_ID]);
+        mbc.write(0x4000, 0xF0);
+        assert_eq!(mbc.read(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn erase_clears_a_sector_or_the_chip() {
+        let mut mbc = flash_mbc6();
+        program(&mut mbc, 0x4000, 0x12);
+        program(&mut mbc, 0x6000, 0x34);
+        erase(&mut mbc, 0x6000, 0x30);
+        assert_eq!([mbc.read(0x4000), mbc.read(0x6000)], [0x12, 0xFF]);
+
+        program(&mut mbc, 0x6000, 0x34);
+        erase(&mut mbc, 0x5555, 0x10);
+        assert_eq!([mbc.read(0x4000), mbc.read(0x6000)], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn flash_persists_after_ram() {
+        let mut mbc = flash_mbc6();
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x11);
+        program(&mut mbc, 0x6000, 0x34);
+        let save = mbc.save_persistent_state();
+        assert_eq!(save.len(), 32 * 1024 + FLASH_SIZE);
+        assert_eq!(save[0], 0x11);
+        assert_eq!(save[32 * 1024 + 17 * MBC6_ROM_BANK_SIZE], 0x34);
+
+        let mut mbc = flash_mbc6();
+        mbc.load_persistent_state(save.clone());
+        assert_eq!(mbc.read(0x6000), 0x34);
+        // An older save without the flash only restores RAM.
+        let mut mbc = flash_mbc6();
+        mbc.load_persistent_state(save[..32 * 1024].to_vec());
+        assert_eq!(mbc.ram[0], 0x11);
+        assert_eq!(mbc.read(0x6000), 0xFF);
+    }
+
+    #[test]
+    fn state_length_does_not_depend_on_the_flash() {
+        let mut mbc = flash_mbc6();
+        let mut state = StateWriter::new();
+        mbc.save_state(&mut state);
+        let erased = state.into_bytes();
+        program(&mut mbc, 0x6000, 0x34);
+        let mut state = StateWriter::new();
+        mbc.save_state(&mut state);
+        let programmed = state.into_bytes();
+        assert_eq!(erased.len(), programmed.len());
+
+        let mut mbc = mbc6();
+        mbc.load_state(&mut StateReader::new(&programmed)).unwrap();
+        assert_eq!(mbc.read(0x6000), 0x34);
+    }
+}
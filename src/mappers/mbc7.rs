@@ -0,0 +1,462 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+/// Accelerometer reading when the Game Boy is held flat.
+const ACCELEROMETER_CENTER: u16 = 0x81D0;
+/// Change in the accelerometer reading for a tilt of one g.
+const ACCELEROMETER_G: f32 = 0x70 as f32;
+const EEPROM_WORDS: usize = 128;
+
+/// Where the EEPROM is within a command, advanced on each rising clock edge.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EepromPhase {
+    /// Waiting for the start bit.
+    Idle,
+    /// Shifting in the 2 opcode and 8 address bits.
+    Command,
+    /// Shifting out the addressed word, then the following ones.
+    Reading,
+    /// Shifting in a word for `address`.
+    Writing,
+    /// Shifting in a word for every address.
+    WritingAll,
+    /// Command complete, ignoring the clock until chip select drops.
+    Done,
+}
+
+impl EepromPhase {
+    const ALL: [EepromPhase; 6] = [
+        EepromPhase::Idle,
+        EepromPhase::Command,
+        EepromPhase::Reading,
+        EepromPhase::Writing,
+        EepromPhase::WritingAll,
+        EepromPhase::Done,
+    ];
+}
+
+/// A 93LC56 serial EEPROM organised as 128 16-bit words, bit-banged by the
+/// game through chip select, clock and data in, with data out read back.
+#[derive(Debug)]
+struct Eeprom93LC56 {
+    words: [u16; EEPROM_WORDS],
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+    write_enabled: bool,
+    phase: EepromPhase,
+    shift: u16,
+    bits: u8,
+    address: u8,
+}
+
+impl Eeprom93LC56 {
+    fn new() -> Eeprom93LC56 {
+        Eeprom93LC56 {
+            words: [0xFFFF; EEPROM_WORDS],
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            write_enabled: false,
+            phase: EepromPhase::Idle,
+            shift: 0,
+            bits: 0,
+            address: 0,
+        }
+    }
+
+    /// Register layout: bit 7 chip select, bit 6 clock, bit 1 data in, bit 0 data out.
+    fn read(&self) -> u8 {
+        (self.chip_select as u8) << 7 | (self.clock as u8) << 6 | (self.data_in as u8) << 1 | self.data_out as u8
+    }
+
+    fn write(&mut self, value: u8) {
+        let chip_select = value & 0x80 != 0;
+        let clock = value & 0x40 != 0;
+        self.data_in = value & 0x02 != 0;
+        if !chip_select {
+            self.phase = EepromPhase::Idle;
+            self.data_out = true;
+        } else if clock && !self.clock {
+            self.clock_edge();
+        }
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn clock_edge(&mut self) {
+        match self.phase {
+            EepromPhase::Idle => {
+                if self.data_in {
+                    self.phase = EepromPhase::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            },
+            EepromPhase::Command => {
+                self.shift = self.shift << 1 | self.data_in as u16;
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.address = self.shift as u8;
+                    self.start_command((self.shift >> 8) as u8 & 0x03);
+                }
+            },
+            EepromPhase::Reading => {
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits == 16 {
+                    self.address = self.address.wrapping_add(1);
+                    self.shift = self.words[self.address as usize % EEPROM_WORDS];
+                    self.bits = 0;
+                }
+            },
+            EepromPhase::Writing | EepromPhase::WritingAll => {
+                self.shift = self.shift << 1 | self.data_in as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.write_enabled {
+                        if self.phase == EepromPhase::Writing {
+                            self.words[self.address as usize % EEPROM_WORDS] = self.shift;
+                        } else {
+                            self.words = [self.shift; EEPROM_WORDS];
+                        }
+                    }
+                    self.finish();
+                }
+            },
+            EepromPhase::Done => {},
+        }
+    }
+
+    fn start_command(&mut self, opcode: u8) {
+        self.shift = 0;
+        self.bits = 0;
+        match opcode {
+            // READ, preceded by a dummy zero bit.
+            0b10 => {
+                self.phase = EepromPhase::Reading;
+                self.shift = self.words[self.address as usize % EEPROM_WORDS];
+                self.data_out = false;
+            },
+            // WRITE
+            0b01 => self.phase = EepromPhase::Writing,
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.words[self.address as usize % EEPROM_WORDS] = 0xFFFF;
+                }
+                self.finish();
+            },
+            // The top two address bits extend the opcode.
+            _ => match self.address >> 6 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    self.finish();
+                },
+                // WRAL
+                0b01 => self.phase = EepromPhase::WritingAll,
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.words = [0xFFFF; EEPROM_WORDS];
+                    }
+                    self.finish();
+                },
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    self.finish();
+                },
+            },
+        }
+    }
+
+    /// Programming is instant, so data out reports ready straight away.
+    fn finish(&mut self) {
+        self.phase = EepromPhase::Done;
+        self.data_out = true;
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn load_bytes(&mut self, bytes: &[u8]) {
+        for (word, chunk) in self.words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_slice(&self.to_bytes());
+        state.write_bool(self.chip_select);
+        state.write_bool(self.clock);
+        state.write_bool(self.data_in);
+        state.write_bool(self.data_out);
+        state.write_bool(self.write_enabled);
+        state.write_u8(EepromPhase::ALL.iter().position(|phase| *phase == self.phase).unwrap() as u8);
+        state.write_u16(self.shift);
+        state.write_u8(self.bits);
+        state.write_u8(self.address);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut bytes = [0; EEPROM_WORDS * 2];
+        state.read_slice(&mut bytes)?;
+        self.load_bytes(&bytes);
+        self.chip_select = state.read_bool()?;
+        self.clock = state.read_bool()?;
+        self.data_in = state.read_bool()?;
+        self.data_out = state.read_bool()?;
+        self.write_enabled = state.read_bool()?;
+        self.phase = *EepromPhase::ALL.get(state.read_u8()? as usize).ok_or(StateError::Invalid("EEPROM phase"))?;
+        self.shift = state.read_u16()?;
+        self.bits = state.read_u8()?;
+        self.address = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/// The MBC7, with a two-axis accelerometer and a 93LC56 EEPROM in place of
+/// SRAM. Both sit behind registers at A000-AFFF, selected by address bits 4-7.
+#[derive(Debug)]
+pub struct MBC7 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    rom_select_register: u8,
+    /// Both enables must be set for A000-AFFF to respond.
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+    /// Host tilt in g, positive to the right and towards the player.
+    tilt_x: f32,
+    tilt_y: f32,
+    latched_x: u16,
+    latched_y: u16,
+    /// Set by erasing the latch, which must happen before it can latch again.
+    latch_ready: bool,
+    eeprom: Eeprom93LC56,
+    num_rom_banks: usize,
+}
+
+impl MBC7 {
+    fn accelerometer(tilt: f32) -> u16 {
+        (ACCELEROMETER_CENTER as f32 + tilt.clamp(-2.0, 2.0) * ACCELEROMETER_G) as u16
+    }
+}
+
+impl Addressable for MBC7 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MBC7 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 128 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        Ok(MBC7 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            rom_select_register: 1,
+            ram_enable_1: false,
+            ram_enable_2: false,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            latched_x: 0x8000,
+            latched_y: 0x8000,
+            latch_ready: false,
+            eeprom: Eeprom93LC56::new(),
+            num_rom_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            0xA000..=0xAFFF if self.ram_enable_1 && self.ram_enable_2 => match (index >> 4) & 0x0F {
+                0x2 => self.latched_x as u8,
+                0x3 => (self.latched_x >> 8) as u8,
+                0x4 => self.latched_y as u8,
+                0x5 => (self.latched_y >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => self.eeprom.read(),
+                _ => 0xFF,
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => unreachable!("Invalid access to MBC7 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.ram_enable_1 = value == 0x0A;
+            },
+            0x2000..=0x3FFF => {
+                self.rom_select_register = value & 0x7F;
+            },
+            0x4000..=0x5FFF => {
+                self.ram_enable_2 = value == 0x40;
+            },
+            0x6000..=0x7FFF => {},
+            0xA000..=0xAFFF if self.ram_enable_1 && self.ram_enable_2 => match (index >> 4) & 0x0F {
+                0x0 if value == 0x55 => {
+                    self.latched_x = 0x8000;
+                    self.latched_y = 0x8000;
+                    self.latch_ready = true;
+                },
+                // The X reading falls as the console tilts to the right.
+                0x1 if value == 0xAA && self.latch_ready => {
+                    self.latched_x = MBC7::accelerometer(-self.tilt_x);
+                    self.latched_y = MBC7::accelerometer(self.tilt_y);
+                    self.latch_ready = false;
+                },
+                0x8 => self.eeprom.write(value),
+                _ => (),
+            },
+            0xA000..=0xBFFF => {},
+            _ => unreachable!("Invalid access to MBC7 cartridge at index {index}"),
+        }
+    }
+
+    /// The EEPROM contents as 128 little-endian words.
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.eeprom.to_bytes()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == EEPROM_WORDS * 2 {
+            self.eeprom.load_bytes(&state);
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt_x = x;
+        self.tilt_y = y;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_select_register);
+        state.write_bool(self.ram_enable_1);
+        state.write_bool(self.ram_enable_2);
+        state.write_u16(self.latched_x);
+        state.write_u16(self.latched_y);
+        state.write_bool(self.latch_ready);
+        self.eeprom.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rom_select_register = state.read_u8()?;
+        self.ram_enable_1 = state.read_bool()?;
+        self.ram_enable_2 = state.read_bool()?;
+        self.latched_x = state.read_u16()?;
+        self.latched_y = state.read_u16()?;
+        self.latch_ready = state.read_bool()?;
+        self.eeprom.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CS: u8 = 0x80;
+    const CLK: u8 = 0x40;
+    const DI: u8 = 0x02;
+
+    /// Clocks `count` bits of `bits` into the EEPROM, most significant first,
+    /// returning data out as sampled after each rising edge.
+    fn clock_bits(eeprom: &mut Eeprom93LC56, bits: u16, count: u32) -> u16 {
+        let mut out = 0;
+        for bit in (0..count).rev() {
+            let data_in = if bits >> bit & 1 == 1 { DI } else { 0 };
+            eeprom.write(CS | data_in);
+            eeprom.write(CS | CLK | data_in);
+            out = out << 1 | (eeprom.read() & 1) as u16;
+        }
+        out
+    }
+
+    /// Selects the chip and sends the start bit, opcode and address.
+    fn command(eeprom: &mut Eeprom93LC56, opcode: u16, address: u8) {
+        eeprom.write(0);
+        clock_bits(eeprom, 1 << 10 | opcode << 8 | address as u16, 11);
+    }
+
+    fn write_word(eeprom: &mut Eeprom93LC56, address: u8, word: u16) {
+        command(eeprom, 0b01, address);
+        clock_bits(eeprom, word, 16);
+        eeprom.write(0);
+    }
+
+    fn read_words(eeprom: &mut Eeprom93LC56, address: u8, count: u32) -> Vec<u16> {
+        command(eeprom, 0b10, address);
+        assert_eq!(eeprom.read() & 1, 0, "missing dummy zero bit");
+        let words = (0..count).map(|_| clock_bits(eeprom, 0, 16)).collect();
+        eeprom.write(0);
+        words
+    }
+
+    #[test]
+    fn writes_need_ewen() {
+        let mut eeprom = Eeprom93LC56::new();
+        write_word(&mut eeprom, 5, 0x1234);
+        assert_eq!(read_words(&mut eeprom, 5, 1), [0xFFFF]);
+
+        command(&mut eeprom, 0b00, 0b1100_0000);
+        eeprom.write(0);
+        write_word(&mut eeprom, 5, 0x1234);
+        assert_eq!(read_words(&mut eeprom, 5, 1), [0x1234]);
+
+        // EWDS protects the contents again.
+        command(&mut eeprom, 0b00, 0b0000_0000);
+        eeprom.write(0);
+        write_word(&mut eeprom, 5, 0xBEEF);
+        assert_eq!(read_words(&mut eeprom, 5, 1), [0x1234]);
+    }
+
+    #[test]
+    fn read_continues_into_the_following_words() {
+        let mut eeprom = Eeprom93LC56::new();
+        command(&mut eeprom, 0b00, 0b1100_0000);
+        eeprom.write(0);
+        write_word(&mut eeprom, 0x7F, 0xBEEF);
+        write_word(&mut eeprom, 0x00, 0xCAFE);
+        write_word(&mut eeprom, 0x01, 0x0001);
+        assert_eq!(read_words(&mut eeprom, 0x7F, 3), [0xBEEF, 0xCAFE, 0x0001]);
+    }
+
+    #[test]
+    fn data_out_reports_ready_after_a_write() {
+        let mut eeprom = Eeprom93LC56::new();
+        command(&mut eeprom, 0b00, 0b1100_0000);
+        eeprom.write(0);
+        command(&mut eeprom, 0b01, 3);
+        clock_bits(&mut eeprom, 0x5A5A, 16);
+        assert_eq!(eeprom.read() & 1, 1);
+        eeprom.write(0);
+        assert_eq!(eeprom.words[3], 0x5A5A);
+    }
+}
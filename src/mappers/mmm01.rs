@@ -0,0 +1,300 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, Addressable, Cartridge, MapperKind};
+
+/// The MMM01 of the official compilations. It starts unmapped, showing the
+/// menu in the last 32 KiB of the ROM with every register fully writable. The
+/// menu then sets the game's outer banks and masks and maps it, after which
+/// only the bits left unmasked can change and the game sees an MBC1.
+#[derive(Debug)]
+pub struct MMM01 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapped: bool,
+    external_ram_enable: bool,
+    /// ROM bank bits 0-4, 5-6 and 7-8.
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    /// RAM bank bits 0-1 and 2-3.
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    /// Bits of `rom_bank_low` (as bits 1-4) and `ram_bank_low` frozen once mapped.
+    rom_bank_mask: u8,
+    ram_bank_mask: u8,
+    mbc1_mode: bool,
+    mbc1_mode_locked: bool,
+    /// Swaps `rom_bank_mid` and `ram_bank_low`, for games with large RAM.
+    multiplex: bool,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+}
+
+impl MMM01 {
+    /// Bits of `rom_bank_low` the game can no longer write.
+    fn fixed_rom_bits(&self) -> u8 {
+        if self.mapped { self.rom_bank_mask << 1 } else { 0 }
+    }
+
+    fn fixed_ram_bits(&self) -> u8 {
+        if self.mapped { self.ram_bank_mask } else { 0 }
+    }
+
+    /// Banks shown at 0000-3FFF and 4000-7FFF.
+    fn rom_bank_numbers(&self) -> (usize, usize) {
+        if !self.mapped {
+            return (0x1FE % self.num_rom_banks, 0x1FF % self.num_rom_banks);
+        }
+        let rom_bank_mid = if self.multiplex { self.ram_bank_low } else { self.rom_bank_mid };
+        let outer = (self.rom_bank_high as usize) << 7 | (rom_bank_mid as usize) << 5;
+        let bank_0 = outer | (self.rom_bank_low & self.fixed_rom_bits()) as usize;
+        let mut bank_1 = outer | self.rom_bank_low as usize;
+        // As on the MBC1, the bank the game selects is never its bank 0.
+        if self.rom_bank_low & !self.fixed_rom_bits() & 0x1F == 0 {
+            bank_1 |= 1;
+        }
+        (bank_0 % self.num_rom_banks, bank_1 % self.num_rom_banks)
+    }
+
+    fn ram_offset(&self, index: u16) -> Option<usize> {
+        if !self.external_ram_enable || self.num_ram_banks == 0 {
+            return None
+        }
+        let ram_bank_low = if self.multiplex { self.rom_bank_mid } else { self.ram_bank_low };
+        let ram_bank_low = if self.mbc1_mode { ram_bank_low } else { 0 };
+        let bank_number = ((self.ram_bank_high << 2) | ram_bank_low) as usize % self.num_ram_banks;
+        Some(bank_number * CARTRIDGE_RAM_SIZE + index as usize - 0xA000)
+    }
+}
+
+impl Addressable for MMM01 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse_rom(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::MMM01 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        // The menu's header does not always describe the whole compilation,
+        // so the size comes from the file.
+        let num_rom_banks = (game_bytes.len() / GB_ROM_BANK_SIZE).max(2);
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 512 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        let num_ram_banks = header.ram_banks()?;
+        let ram_size = num_ram_banks * CARTRIDGE_RAM_SIZE;
+        println!("Ram with {num_ram_banks} banks, total {} KB", ram_size / 1024);
+
+        Ok(MMM01 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: vec![0; ram_size],
+            mapped: false,
+            external_ram_enable: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_mask: 0,
+            mbc1_mode: false,
+            mbc1_mode_locked: false,
+            multiplex: false,
+            num_rom_banks,
+            num_ram_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        let (bank_0, bank_1) = self.rom_bank_numbers();
+        match index {
+            0..=0x3FFF => self.rom[bank_0 * GB_ROM_BANK_SIZE + index as usize],
+            0x4000..=0x7FFF => self.rom[bank_1 * GB_ROM_BANK_SIZE + index as usize - 0x4000],
+            0xA000..=0xBFFF => match self.ram_offset(index) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => unreachable!("Invalid access to MMM01 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                self.external_ram_enable = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            },
+            0x2000..=0x3FFF => {
+                let fixed = self.fixed_rom_bits();
+                self.rom_bank_low = (self.rom_bank_low & fixed) | (value & 0x1F & !fixed);
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x03;
+                }
+            },
+            0x4000..=0x5FFF => {
+                let fixed = self.fixed_ram_bits();
+                self.ram_bank_low = (self.ram_bank_low & fixed) | (value & 0x03 & !fixed);
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                    self.mbc1_mode_locked = value & 0x40 != 0;
+                }
+            },
+            0x6000..=0x7FFF => {
+                if !self.mapped || !self.mbc1_mode_locked {
+                    self.mbc1_mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0F;
+                    self.multiplex = value & 0x40 != 0;
+                }
+            },
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(index) {
+                    self.ram[offset] = value;
+                }
+            },
+            _ => unreachable!("Invalid access to MMM01 cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_state(&mut self, state: Vec<u8>) {
+        if state.len() == self.ram.len() {
+            self.ram = state;
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_bool(self.mapped);
+        state.write_bool(self.external_ram_enable);
+        state.write_u8(self.rom_bank_low);
+        state.write_u8(self.rom_bank_mid);
+        state.write_u8(self.rom_bank_high);
+        state.write_u8(self.ram_bank_low);
+        state.write_u8(self.ram_bank_high);
+        state.write_u8(self.rom_bank_mask);
+        state.write_u8(self.ram_bank_mask);
+        state.write_bool(self.mbc1_mode);
+        state.write_bool(self.mbc1_mode_locked);
+        state.write_bool(self.multiplex);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.mapped = state.read_bool()?;
+        self.external_ram_enable = state.read_bool()?;
+        self.rom_bank_low = state.read_u8()?;
+        self.rom_bank_mid = state.read_u8()?;
+        self.rom_bank_high = state.read_u8()?;
+        self.ram_bank_low = state.read_u8()?;
+        self.ram_bank_high = state.read_u8()?;
+        self.rom_bank_mask = state.read_u8()?;
+        self.ram_bank_mask = state.read_u8()?;
+        self.mbc1_mode = state.read_bool()?;
+        self.mbc1_mode_locked = state.read_bool()?;
+        self.multiplex = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::NINTENDO_LOGO;
+    use crate::mappers::tests::test_rom;
+
+    const MENU_OFFSET: usize = 0x100000 - 0x8000;
+
+    /// A 1 MiB compilation with 32 KiB of RAM, with the MMM01 header on the
+    /// menu in the last 32 KiB.
+    fn mmm01_rom() -> Vec<u8> {
+        let mut rom = test_rom(0x01, 0x05, 0x00);
+        let header = &mut rom[MENU_OFFSET..MENU_OFFSET + HEADER_END];
+        header[NINTENDO_LOGO_ADDR].copy_from_slice(&NINTENDO_LOGO);
+        header[CARTRIDGE_TYPE_ADDR] = 0x0D;
+        header[RAM_SIZE_ADDR] = 0x03;
+        header[HEADER_CHECKSUM_ADDR] = header[TITLE_ADDR.start..HEADER_CHECKSUM_ADDR].iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn header_is_found_on_the_menu() {
+        let rom = mmm01_rom();
+        assert_eq!(CartridgeHeader::locate(&rom), MENU_OFFSET);
+        assert_eq!(CartridgeHeader::parse_rom(&rom).unwrap().cartridge().unwrap().mapper, MapperKind::MMM01);
+        // The game header at 0 names an MBC1.
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap().cartridge_type, 0x01);
+    }
+
+    #[test]
+    fn starts_showing_the_menu() {
+        let mut mbc = MMM01::new(mmm01_rom()).unwrap();
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [62, 63]);
+        // Bank writes do not apply until the game is mapped.
+        mbc.write(0x2000, 0x05);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [62, 63]);
+    }
+
+    #[test]
+    fn masks_freeze_once_mapped() {
+        let mut mbc = MMM01::new(mmm01_rom()).unwrap();
+        // Outer bank 0x20, ROM bank bits 3-4 fixed to 0x08 and RAM bank bits
+        // fixed to 2, for an 8-bank game at 0x28.
+        mbc.write(0x2000, 0x28);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0x6000, 0x0C << 2 | 0x01);
+        mbc.write(0x0000, 0x40 | 0x30 | 0x0A);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [0x28, 0x29]);
+
+        mbc.write(0x2000, 0x03);
+        assert_eq!(mbc.read(0x4200), 0x2B);
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(mbc.read(0x4200), 0x2F);
+        // The outer bank bits and the mapping itself are frozen too.
+        mbc.write(0x2000, 0x60);
+        assert_eq!(mbc.read(0x4200), 0x29);
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read(0x0200), 0x28);
+
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x55);
+        assert_eq!(mbc.ram[2 * CARTRIDGE_RAM_SIZE], 0x55);
+    }
+
+    #[test]
+    fn multiplex_swaps_outer_rom_and_ram_bank_bits() {
+        let mut mbc = MMM01::new(mmm01_rom()).unwrap();
+        mbc.write(0x2000, 0x40);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x6000, 0x40 | 0x01);
+        mbc.write(0x0000, 0x40 | 0x0A);
+        assert_eq!(mbc.read(0x0200), 0x20);
+        mbc.write(0xA000, 0x55);
+        assert_eq!(mbc.ram[2 * CARTRIDGE_RAM_SIZE], 0x55);
+        // With no RAM mask the game's RAM bank writes pick the outer ROM bank.
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0x0200), 0x00);
+    }
+}
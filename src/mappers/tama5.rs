@@ -0,0 +1,482 @@
+use crate::cartridge::{CartridgeHeader, LoadError};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{rom_banks, unix_time, Addressable, Cartridge, MapperKind};
+
+const TAMA5_RAM_SIZE: usize = 32;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// Unix timestamp, the 13 clock digits and the timer enable byte.
+const TAMA5_FOOTER_SIZE: usize = 22;
+
+const ROM_BANK_LOW_REGISTER: usize = 0x0;
+const ROM_BANK_HIGH_REGISTER: usize = 0x1;
+const DATA_IN_LOW_REGISTER: usize = 0x4;
+const DATA_IN_HIGH_REGISTER: usize = 0x5;
+/// Bit 0 is bit 4 of the address, bits 1-3 the command.
+const COMMAND_REGISTER: usize = 0x6;
+/// Low nibble of the address. Writing it runs the command.
+const ADDRESS_REGISTER: usize = 0x7;
+const READY_REGISTER: usize = 0xA;
+const DATA_OUT_LOW_REGISTER: usize = 0xC;
+const DATA_OUT_HIGH_REGISTER: usize = 0xD;
+
+const COMMAND_RAM_WRITE: u8 = 0x0;
+const COMMAND_RAM_READ: u8 = 0x1;
+const COMMAND_CONTROL: u8 = 0x2;
+const COMMAND_CLOCK: u8 = 0x4;
+
+/// Addresses of the control command.
+const CONTROL_STOP_TIMER: u8 = 0x00;
+const CONTROL_START_TIMER: u8 = 0x01;
+const CONTROL_SET_MINUTES: u8 = 0x04;
+const CONTROL_SET_HOURS: u8 = 0x05;
+
+/// Clock digits, ones before tens, in the order of the TC8521's first page.
+const SECONDS: usize = 0;
+const MINUTES: usize = 2;
+const HOURS: usize = 4;
+const WEEKDAY: usize = 6;
+const DAY: usize = 7;
+const MONTH: usize = 9;
+const YEAR: usize = 11;
+const CLOCK_DIGITS: usize = 13;
+
+/// The TAMA6's calendar clock, kept as the BCD digits the game reads and writes.
+#[derive(Debug, Clone, Copy)]
+struct Tama6Clock {
+    digits: [u8; CLOCK_DIGITS],
+}
+
+impl Tama6Clock {
+    fn new() -> Tama6Clock {
+        let mut clock = Tama6Clock { digits: [0; CLOCK_DIGITS] };
+        clock.set(DAY, 1);
+        clock.set(MONTH, 1);
+        clock
+    }
+
+    /// The two-digit value whose ones digit is at `digit`.
+    fn get(&self, digit: usize) -> u64 {
+        self.digits[digit] as u64 + 10 * self.digits[digit + 1] as u64
+    }
+
+    fn set(&mut self, digit: usize, value: u64) {
+        self.digits[digit] = (value % 10) as u8;
+        self.digits[digit + 1] = (value / 10 % 10) as u8;
+    }
+
+    fn days_in_month(&self) -> u64 {
+        match self.get(MONTH) {
+            2 if self.get(YEAR).is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn next_day(&mut self) {
+        self.digits[WEEKDAY] = (self.digits[WEEKDAY] + 1) % 7;
+        self.set(DAY, self.get(DAY) + 1);
+        if self.get(DAY) <= self.days_in_month() {
+            return;
+        }
+        self.set(DAY, 1);
+        self.set(MONTH, self.get(MONTH) + 1);
+        if self.get(MONTH) > 12 {
+            self.set(MONTH, 1);
+            self.set(YEAR, (self.get(YEAR) + 1) % 100);
+        }
+    }
+
+    /// Runs the clock forward by `seconds` of host time.
+    fn advance(&mut self, seconds: u64) {
+        let time_of_day = self.get(SECONDS) + 60 * self.get(MINUTES) + 3600 * self.get(HOURS) + seconds;
+        self.set(SECONDS, time_of_day % 60);
+        self.set(MINUTES, time_of_day / 60 % 60);
+        self.set(HOURS, time_of_day / 3600 % 24);
+        for _ in 0..time_of_day / SECONDS_PER_DAY {
+            self.next_day();
+        }
+    }
+}
+
+/// Bandai's TAMA5, from Tamagotchi 3. The game talks to it through two
+/// locations: writes to A001 select one of its nibble registers and A000 reads
+/// or writes the selected one. ROM banks and commands for the TAMA6
+/// microcontroller, which holds 32 bytes of memory and the clock, all go
+/// through these registers.
+///
+/// Commands run when the address low nibble is written. 0 and 1 write and read
+/// a memory byte, 2 starts or stops the timer or sets the minutes or hours from
+/// a BCD byte, and 4 reads or writes the clock digit selected by the low data
+/// nibble, as address 2 or 0 respectively.
+#[derive(Debug)]
+pub struct TAMA5 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    ram: [u8; TAMA5_RAM_SIZE],
+    registers: [u8; 16],
+    register_select: u8,
+    clock: Tama6Clock,
+    timer_enabled: bool,
+    ticks_since_last_second: u32,
+    num_rom_banks: usize,
+}
+
+impl TAMA5 {
+    fn command(&self) -> u8 {
+        self.registers[COMMAND_REGISTER] >> 1
+    }
+
+    fn address(&self) -> usize {
+        (self.registers[COMMAND_REGISTER] as usize & 0x01) << 4 | self.registers[ADDRESS_REGISTER] as usize
+    }
+
+    fn data_in(&self) -> u8 {
+        self.registers[DATA_IN_HIGH_REGISTER] << 4 | self.registers[DATA_IN_LOW_REGISTER]
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.registers[ROM_BANK_HIGH_REGISTER] as usize & 0x01) << 4 | self.registers[ROM_BANK_LOW_REGISTER] as usize) % self.num_rom_banks
+    }
+
+    /// The byte the microcontroller answers with, read a nibble at a time.
+    fn data_out(&self) -> u8 {
+        match self.command() {
+            COMMAND_RAM_READ => self.ram[self.address()],
+            COMMAND_CLOCK if self.registers[ADDRESS_REGISTER] == 0x2 => {
+                self.clock.digits.get(self.registers[DATA_IN_LOW_REGISTER] as usize).copied().unwrap_or(0)
+            },
+            _ => 0x00,
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let data = self.data_in();
+        match self.command() {
+            COMMAND_RAM_WRITE => self.ram[self.address()] = data,
+            COMMAND_CONTROL => match self.address() as u8 {
+                CONTROL_STOP_TIMER => self.timer_enabled = false,
+                CONTROL_START_TIMER => {
+                    self.timer_enabled = true;
+                    self.ticks_since_last_second = 0;
+                },
+                CONTROL_SET_MINUTES => {
+                    self.clock.digits[MINUTES] = data & 0x0F;
+                    self.clock.digits[MINUTES + 1] = data >> 4;
+                    self.clock.set(SECONDS, 0);
+                },
+                CONTROL_SET_HOURS => {
+                    self.clock.digits[HOURS] = data & 0x0F;
+                    self.clock.digits[HOURS + 1] = data >> 4;
+                },
+                _ => (),
+            },
+            COMMAND_CLOCK if self.registers[ADDRESS_REGISTER] == 0x0 => {
+                let digit = self.registers[DATA_IN_LOW_REGISTER] as usize;
+                if digit < CLOCK_DIGITS {
+                    self.clock.digits[digit] = self.registers[DATA_IN_HIGH_REGISTER];
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Addressable for TAMA5 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&game_bytes)?;
+        let cartridge_type = header.cartridge()?;
+        if cartridge_type.mapper != MapperKind::TAMA5 {
+            return Err(LoadError::UnsupportedMapper(cartridge_type));
+        }
+
+        let num_rom_banks = header.rom_banks()?;
+        let rom_size = num_rom_banks * 16;
+        println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+        if num_rom_banks > 32 {
+            return Err(LoadError::UnsupportedRomSize { cartridge: cartridge_type, kib: rom_size });
+        }
+
+        // The memory is inside the TAMA6, whatever the header says.
+        println!("Ram in the TAMA6, total {TAMA5_RAM_SIZE} bytes");
+
+        let mut registers = [0; 16];
+        registers[ROM_BANK_LOW_REGISTER] = 1;
+        Ok(TAMA5 {
+            cartridge: cartridge_type,
+            rom: rom_banks(&game_bytes, num_rom_banks)?,
+            ram: [0; TAMA5_RAM_SIZE],
+            registers,
+            register_select: 0,
+            clock: Tama6Clock::new(),
+            timer_enabled: true,
+            ticks_since_last_second: 0,
+            num_rom_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x3FFF => self.rom[index as usize],
+            0x4000..=0x7FFF => self.rom[self.rom_bank() * GB_ROM_BANK_SIZE + index as usize - 0x4000],
+            0xA000..=0xBFFF if index & 1 == 1 => 0xFF,
+            0xA000..=0xBFFF => match self.register_select as usize {
+                // Commands complete immediately, so the chip is always ready.
+                READY_REGISTER => 0xF1,
+                DATA_OUT_LOW_REGISTER => 0xF0 | (self.data_out() & 0x0F),
+                DATA_OUT_HIGH_REGISTER => 0xF0 | (self.data_out() >> 4),
+                _ => 0xFF,
+            },
+            _ => unreachable!("Invalid access to TAMA5 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x7FFF => {},
+            0xA000..=0xBFFF if index & 1 == 1 => self.register_select = value & 0x0F,
+            0xA000..=0xBFFF => {
+                let register = self.register_select as usize;
+                self.registers[register] = value & 0x0F;
+                if register == ADDRESS_REGISTER {
+                    self.execute_command();
+                }
+            },
+            _ => unreachable!("Invalid access to TAMA5 cartridge at index {index}"),
+        }
+    }
+
+    /// The 32 bytes of memory followed by a 22-byte clock footer: the host
+    /// UNIX time of the save as a u64, the 13 clock digits and whether the
+    /// timer runs.
+    fn save_persistent_state(&self) -> Vec<u8> {
+        let mut state = self.ram.to_vec();
+        state.extend_from_slice(&unix_time().to_le_bytes());
+        state.extend_from_slice(&self.clock.digits);
+        state.push(self.timer_enabled as u8);
+        state
+    }
+
+    /// Accepts saves with or without the clock footer. A running clock is
+    /// caught up with the host time that passed since the save was written.
+    fn load_persistent_state(&mut self, mut state: Vec<u8>) {
+        if state.len() < TAMA5_RAM_SIZE {
+            return;
+        }
+        let footer = state.split_off(TAMA5_RAM_SIZE);
+        self.ram.copy_from_slice(&state);
+        if footer.len() != TAMA5_FOOTER_SIZE {
+            return;
+        }
+        let timestamp = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        for (digit, value) in self.clock.digits.iter_mut().zip(&footer[8..8 + CLOCK_DIGITS]) {
+            *digit = value & 0x0F;
+        }
+        self.timer_enabled = footer[8 + CLOCK_DIGITS] != 0;
+        if self.timer_enabled {
+            self.clock.advance(unix_time().saturating_sub(timestamp));
+        }
+    }
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, nticks: u8) {
+        if !self.timer_enabled {
+            return;
+        }
+        self.ticks_since_last_second += nticks as u32;
+        if self.ticks_since_last_second >= CLOCK_FREQ_HZ {
+            self.ticks_since_last_second -= CLOCK_FREQ_HZ;
+            self.clock.advance(1);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_slice(&self.ram);
+        state.write_slice(&self.registers);
+        state.write_u8(self.register_select);
+        state.write_slice(&self.clock.digits);
+        state.write_bool(self.timer_enabled);
+        state.write_u32(self.ticks_since_last_second);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_slice(&mut self.ram)?;
+        state.read_slice(&mut self.registers)?;
+        self.register_select = state.read_u8()?;
+        state.read_slice(&mut self.clock.digits)?;
+        self.timer_enabled = state.read_bool()?;
+        self.ticks_since_last_second = state.read_u32()?;
+        if self.registers.iter().chain(&self.clock.digits).any(|nibble| *nibble > 0x0F) || self.register_select > 0x0F {
+            return Err(StateError::Invalid("TAMA5 registers"));
+        }
+        if self.ticks_since_last_second >= CLOCK_FREQ_HZ {
+            return Err(StateError::Invalid("TAMA5 sub-second counter"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    fn tama5() -> TAMA5 {
+        TAMA5::new(test_rom(0xFD, 0x04, 0x00)).unwrap()
+    }
+
+    fn write_register(mbc: &mut TAMA5, register: usize, value: u8) {
+        mbc.write(0xA001, register as u8);
+        mbc.write(0xA000, value);
+    }
+
+    fn read_register(mbc: &mut TAMA5, register: usize) -> u8 {
+        mbc.write(0xA001, register as u8);
+        mbc.read(0xA000)
+    }
+
+    /// Loads the data and command registers, then runs the command by writing
+    /// the address low nibble.
+    fn run_command(mbc: &mut TAMA5, command: u8, address: usize, data: u8) {
+        write_register(mbc, DATA_IN_LOW_REGISTER, data & 0x0F);
+        write_register(mbc, DATA_IN_HIGH_REGISTER, data >> 4);
+        write_register(mbc, COMMAND_REGISTER, command << 1 | (address >> 4) as u8);
+        write_register(mbc, ADDRESS_REGISTER, address as u8 & 0x0F);
+    }
+
+    fn data_out(mbc: &mut TAMA5) -> u8 {
+        (read_register(mbc, DATA_OUT_HIGH_REGISTER) & 0x0F) << 4 | (read_register(mbc, DATA_OUT_LOW_REGISTER) & 0x0F)
+    }
+
+    fn clock(day: u64, month: u64, year: u64) -> Tama6Clock {
+        let mut clock = Tama6Clock::new();
+        clock.set(DAY, day);
+        clock.set(MONTH, month);
+        clock.set(YEAR, year);
+        clock
+    }
+
+    #[test]
+    fn registers_are_selected_through_a001() {
+        let mut mbc = tama5();
+        assert_eq!(mbc.read(0x4200), 1);
+        write_register(&mut mbc, ROM_BANK_LOW_REGISTER, 0x3);
+        write_register(&mut mbc, ROM_BANK_HIGH_REGISTER, 0x1);
+        assert_eq!(mbc.read(0x4200), 0x13);
+        // Only the low nibble of a register is kept.
+        write_register(&mut mbc, ROM_BANK_LOW_REGISTER, 0xF5);
+        assert_eq!(mbc.read(0x4200), 0x15);
+
+        assert_eq!(read_register(&mut mbc, READY_REGISTER), 0xF1);
+        assert_eq!(read_register(&mut mbc, ROM_BANK_LOW_REGISTER), 0xFF);
+        assert_eq!(mbc.read(0xA001), 0xFF);
+    }
+
+    #[test]
+    fn memory_is_written_and_read_with_commands() {
+        let mut mbc = tama5();
+        run_command(&mut mbc, COMMAND_RAM_WRITE, 0x05, 0xA7);
+        run_command(&mut mbc, COMMAND_RAM_WRITE, 0x1F, 0x3C);
+        assert_eq!((mbc.ram[0x05], mbc.ram[0x1F]), (0xA7, 0x3C));
+
+        run_command(&mut mbc, COMMAND_RAM_READ, 0x05, 0x00);
+        assert_eq!(data_out(&mut mbc), 0xA7);
+        assert_eq!(read_register(&mut mbc, DATA_OUT_HIGH_REGISTER), 0xFA);
+        run_command(&mut mbc, COMMAND_RAM_READ, 0x1F, 0x00);
+        assert_eq!(data_out(&mut mbc), 0x3C);
+    }
+
+    #[test]
+    fn clock_digits_are_read_and_written() {
+        let mut mbc = tama5();
+        // The data low nibble picks the digit and the high nibble is its value.
+        run_command(&mut mbc, COMMAND_CLOCK, 0x0, 0x70 | MINUTES as u8);
+        run_command(&mut mbc, COMMAND_CLOCK, 0x0, 0x40 | (MINUTES + 1) as u8);
+        assert_eq!(mbc.clock.get(MINUTES), 47);
+        run_command(&mut mbc, COMMAND_CLOCK, 0x2, MINUTES as u8);
+        assert_eq!(data_out(&mut mbc), 7);
+        run_command(&mut mbc, COMMAND_CLOCK, 0x2, (MINUTES + 1) as u8);
+        assert_eq!(data_out(&mut mbc), 4);
+
+        run_command(&mut mbc, COMMAND_CONTROL, CONTROL_SET_HOURS as usize, 0x21);
+        assert_eq!(mbc.clock.get(HOURS), 21);
+        mbc.clock.set(SECONDS, 30);
+        run_command(&mut mbc, COMMAND_CONTROL, CONTROL_SET_MINUTES as usize, 0x15);
+        assert_eq!((mbc.clock.get(MINUTES), mbc.clock.get(SECONDS)), (15, 0));
+
+        run_command(&mut mbc, COMMAND_CONTROL, CONTROL_STOP_TIMER as usize, 0x00);
+        for _ in 0..CLOCK_FREQ_HZ / 128 + 1 {
+            mbc.tick(128);
+        }
+        assert_eq!(mbc.clock.get(SECONDS), 0);
+        run_command(&mut mbc, COMMAND_CONTROL, CONTROL_START_TIMER as usize, 0x00);
+        for _ in 0..CLOCK_FREQ_HZ / 128 + 1 {
+            mbc.tick(128);
+        }
+        assert_eq!(mbc.clock.get(SECONDS), 1);
+    }
+
+    #[test]
+    fn clock_advances_across_days_months_and_years() {
+        let mut time = clock(1, 1, 0);
+        time.set(HOURS, 23);
+        time.set(MINUTES, 59);
+        time.set(SECONDS, 59);
+        time.advance(1);
+        assert_eq!([time.get(HOURS), time.get(MINUTES), time.get(SECONDS), time.get(DAY)], [0, 0, 0, 2]);
+        assert_eq!(time.digits[WEEKDAY], 1);
+
+        let mut time = clock(30, 4, 0);
+        time.advance(SECONDS_PER_DAY);
+        assert_eq!((time.get(DAY), time.get(MONTH)), (1, 5));
+
+        let mut time = clock(28, 2, 24);
+        time.advance(SECONDS_PER_DAY);
+        assert_eq!((time.get(DAY), time.get(MONTH)), (29, 2));
+        let mut time = clock(28, 2, 23);
+        time.advance(SECONDS_PER_DAY);
+        assert_eq!((time.get(DAY), time.get(MONTH)), (1, 3));
+
+        let mut time = clock(31, 12, 99);
+        time.advance(3 * SECONDS_PER_DAY);
+        assert_eq!((time.get(DAY), time.get(MONTH), time.get(YEAR)), (3, 1, 0));
+        assert_eq!(time.digits[WEEKDAY], 3);
+    }
+
+    #[test]
+    fn footer_round_trips_and_catches_up() {
+        let mut mbc = tama5();
+        mbc.ram[0x10] = 0x42;
+        mbc.clock = clock(31, 1, 24);
+        mbc.clock.set(HOURS, 23);
+        mbc.clock.set(MINUTES, 55);
+        let mut save = mbc.save_persistent_state();
+        assert_eq!(save.len(), TAMA5_RAM_SIZE + TAMA5_FOOTER_SIZE);
+        let ten_minutes_ago = unix_time() - 600;
+        save[TAMA5_RAM_SIZE..][..8].copy_from_slice(&ten_minutes_ago.to_le_bytes());
+
+        let mut mbc = tama5();
+        mbc.load_persistent_state(save.clone());
+        assert_eq!(mbc.ram[0x10], 0x42);
+        assert!(mbc.timer_enabled);
+        assert_eq!([mbc.clock.get(HOURS), mbc.clock.get(MINUTES), mbc.clock.get(DAY), mbc.clock.get(MONTH)], [0, 5, 1, 2]);
+
+        // A stopped clock is restored as saved.
+        *save.last_mut().unwrap() = 0;
+        let mut mbc = tama5();
+        mbc.load_persistent_state(save.clone());
+        assert!(!mbc.timer_enabled);
+        assert_eq!([mbc.clock.get(HOURS), mbc.clock.get(MINUTES), mbc.clock.get(DAY)], [23, 55, 31]);
+
+        // A save without the footer only restores memory.
+        let mut mbc = tama5();
+        mbc.load_persistent_state(save[..TAMA5_RAM_SIZE].to_vec());
+        assert_eq!(mbc.ram[0x10], 0x42);
+        assert_eq!(mbc.clock.get(DAY), 1);
+    }
+}
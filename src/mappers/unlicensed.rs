@@ -0,0 +1,416 @@
+use crate::cartridge::{CartridgeHeader, LoadError, NINTENDO_LOGO};
+use crate::constants::*;
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+use super::{Addressable, Cartridge, MapperKind};
+
+/// Unlicensed headers are not to be trusted, so ROM sizes come from the file.
+fn rom_from_file(game_bytes: Vec<u8>, bank_size: usize, max_banks: usize, cartridge: Cartridge) -> Result<(Vec<u8>, usize), LoadError> {
+    CartridgeHeader::parse(&game_bytes)?;
+    let num_rom_banks = (game_bytes.len() / bank_size).max(1);
+    let rom_size = num_rom_banks * bank_size / 1024;
+    println!("Rom with {num_rom_banks} banks, total {rom_size} KB");
+    if num_rom_banks > max_banks {
+        return Err(LoadError::UnsupportedRomSize { cartridge, kib: rom_size });
+    }
+    if game_bytes.len() < num_rom_banks * bank_size {
+        return Err(LoadError::Truncated { len: game_bytes.len(), expected: num_rom_banks * bank_size });
+    }
+    let mut rom = game_bytes;
+    rom.truncate(num_rom_banks * bank_size);
+    Ok((rom, num_rom_banks))
+}
+
+/// Swaps address lines A0 with A6 and A1 with A4, as a locked Sachen mapper
+/// does for reads of the header.
+fn sachen_scramble(address: usize) -> usize {
+    let bit = |n: usize| (address >> n) & 1;
+    (address & !0x53) | bit(6) | bit(4) << 1 | bit(1) << 4 | bit(0) << 6
+}
+
+/// The header as the boot ROM reads it through a locked Sachen mapper. The
+/// ROM itself holds Sachen's logo at 0x104, with Nintendo's scrambled around it.
+pub fn sachen_header(game_bytes: &[u8]) -> Vec<u8> {
+    (0..HEADER_END).map(|address| {
+        let address = if address >= 0x100 { sachen_scramble(address) } else { address };
+        game_bytes.get(address).copied().unwrap_or(0xFF)
+    }).collect()
+}
+
+/// Guesses the mapper of unlicensed cartridges, whose type byte is either
+/// nonstandard or borrowed from a licensed mapper they do not have.
+pub fn detect_unlicensed(game_bytes: &[u8]) -> Option<MapperKind> {
+    if game_bytes.len() < HEADER_END {
+        return None;
+    }
+    if game_bytes[NINTENDO_LOGO_ADDR] != NINTENDO_LOGO && sachen_header(game_bytes)[NINTENDO_LOGO_ADDR] == NINTENDO_LOGO {
+        // The MMC2 also carries the logo with A7 set, for the CGB boot ROM's second check.
+        let cgb_logo = NINTENDO_LOGO_ADDR.start + 0x80..NINTENDO_LOGO_ADDR.end + 0x80;
+        return Some(match game_bytes.get(cgb_logo) {
+            Some(logo) if logo == NINTENDO_LOGO => MapperKind::SachenMMC2,
+            _ => MapperKind::SachenMMC1,
+        });
+    }
+    let cartridge_type = game_bytes[CARTRIDGE_TYPE_ADDR];
+    let first_banks = &game_bytes[..game_bytes.len().min(0x8000)];
+    let mentions = |text: &[u8]| first_banks.windows(text.len()).any(|window| window == text);
+    if matches!(cartridge_type, 0x00 | 0xC0) && game_bytes.len() > 0x8000 && (mentions(b"WISDOM TREE") || mentions(b"WISDOM\0TREE")) {
+        return Some(MapperKind::WisdomTree);
+    }
+    // Mani's 4-in-1 claims an MBC3 with a clock and RAM, but declares no RAM.
+    if cartridge_type == 0x10 && game_bytes[RAM_SIZE_ADDR] == 0x00 && game_bytes.len() == 256 * 1024 {
+        return Some(MapperKind::M161);
+    }
+    None
+}
+
+/// Wisdom Tree's mapper. Any write to 0000-3FFF selects the 32 KiB bank shown
+/// at 0000-7FFF with the low byte of its address, the value is ignored.
+#[derive(Debug)]
+pub struct WisdomTree {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    rom_select_register: u8,
+    num_rom_banks: usize,
+}
+
+impl Addressable for WisdomTree {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let cartridge = Cartridge::new(MapperKind::WisdomTree);
+        let (rom, num_rom_banks) = rom_from_file(game_bytes, 2 * GB_ROM_BANK_SIZE, 256, cartridge)?;
+        Ok(WisdomTree {
+            cartridge,
+            rom,
+            rom_select_register: 0,
+            num_rom_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * 2 * GB_ROM_BANK_SIZE + index as usize]
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => unreachable!("Invalid access to WisdomTree cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, _value: u8) {
+        match index {
+            0..=0x3FFF => self.rom_select_register = index as u8,
+            0x4000..=0x7FFF | 0xA000..=0xBFFF => {},
+            _ => unreachable!("Invalid access to WisdomTree cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_persistent_state(&mut self, _state: Vec<u8>) {}
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_select_register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rom_select_register = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/// Sachen's MMC1 and MMC2. A base bank and a mask, both only writable while
+/// the selected bank has bits 4 and 5 set, let multicarts confine each game to
+/// an outer bank: 0000-3FFF shows `base & mask` and 4000-7FFF fills the masked
+/// out bits from the selected bank.
+///
+/// Both start locked, scrambling the header so the boot ROM finds Nintendo's
+/// logo, and the MMC2 has a second locked stage for the CGB boot ROM. Emulation
+/// starts after the boot ROM has unlocked them, so only `detect_unlicensed`
+/// deals with the scrambling and the two behave the same.
+#[derive(Debug)]
+pub struct Sachen {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    base_bank: u8,
+    bank_mask: u8,
+    rom_select_register: u8,
+    num_rom_banks: usize,
+}
+
+impl Sachen {
+    pub fn with_kind(game_bytes: Vec<u8>, mapper: MapperKind) -> Result<Sachen, LoadError> {
+        let cartridge = Cartridge::new(mapper);
+        let (rom, num_rom_banks) = rom_from_file(game_bytes, GB_ROM_BANK_SIZE, 256, cartridge)?;
+        Ok(Sachen {
+            cartridge,
+            rom,
+            base_bank: 0,
+            bank_mask: 0,
+            rom_select_register: 1,
+            num_rom_banks,
+        })
+    }
+
+    fn outer_registers_writable(&self) -> bool {
+        self.rom_select_register & 0x30 == 0x30
+    }
+}
+
+impl Addressable for Sachen {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let mapper = match detect_unlicensed(&game_bytes) {
+            Some(MapperKind::SachenMMC2) => MapperKind::SachenMMC2,
+            _ => MapperKind::SachenMMC1,
+        };
+        Sachen::with_kind(game_bytes, mapper)
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        let outer_bank = self.base_bank & self.bank_mask;
+        match index {
+            0..=0x3FFF => {
+                let bank_number = outer_bank as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize]
+            },
+            0x4000..=0x7FFF => {
+                let bank_number = (outer_bank | (self.rom_select_register & !self.bank_mask)) as usize % self.num_rom_banks;
+                self.rom[bank_number * GB_ROM_BANK_SIZE + index as usize - 0x4000]
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => unreachable!("Invalid access to Sachen cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0..=0x1FFF => {
+                if self.outer_registers_writable() {
+                    self.base_bank = value;
+                }
+            },
+            0x2000..=0x3FFF => self.rom_select_register = value.max(1),
+            0x4000..=0x5FFF => {
+                if self.outer_registers_writable() {
+                    self.bank_mask = value;
+                }
+            },
+            0x6000..=0x7FFF | 0xA000..=0xBFFF => {},
+            _ => unreachable!("Invalid access to Sachen cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_persistent_state(&mut self, _state: Vec<u8>) {}
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.base_bank);
+        state.write_u8(self.bank_mask);
+        state.write_u8(self.rom_select_register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.base_bank = state.read_u8()?;
+        self.bank_mask = state.read_u8()?;
+        self.rom_select_register = state.read_u8()?;
+        Ok(())
+    }
+}
+
+/// Mani's M161. The first write to 4000-5FFF selects the 32 KiB bank shown at
+/// 0000-7FFF, after which the register is locked until the next power cycle.
+#[derive(Debug)]
+pub struct M161 {
+    cartridge: Cartridge,
+    rom: Vec<u8>,
+    rom_select_register: u8,
+    locked: bool,
+    num_rom_banks: usize,
+}
+
+impl Addressable for M161 {
+    fn new(game_bytes: Vec<u8>) -> Result<Self, LoadError> {
+        let cartridge = Cartridge::new(MapperKind::M161);
+        let (rom, num_rom_banks) = rom_from_file(game_bytes, 2 * GB_ROM_BANK_SIZE, 8, cartridge)?;
+        Ok(M161 {
+            cartridge,
+            rom,
+            rom_select_register: 0,
+            locked: false,
+            num_rom_banks,
+        })
+    }
+
+    fn read(&self, index: u16) -> u8 {
+        match index {
+            0..=0x7FFF => {
+                let bank_number = self.rom_select_register as usize % self.num_rom_banks;
+                self.rom[bank_number * 2 * GB_ROM_BANK_SIZE + index as usize]
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => unreachable!("Invalid access to M161 cartridge at index {index}"),
+        }
+    }
+
+    fn write(&mut self, index: u16, value: u8) {
+        match index {
+            0x4000..=0x5FFF => {
+                if !self.locked {
+                    self.rom_select_register = value & 0x07;
+                    self.locked = true;
+                }
+            },
+            0..=0x3FFF | 0x6000..=0x7FFF | 0xA000..=0xBFFF => {},
+            _ => unreachable!("Invalid access to M161 cartridge at index {index}"),
+        }
+    }
+
+    fn save_persistent_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_persistent_state(&mut self, _state: Vec<u8>) {}
+
+    fn cartridge_type(&self) -> Option<Cartridge> {
+        Some(self.cartridge)
+    }
+
+    fn tick(&mut self, _nticks: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rom_select_register);
+        state.write_bool(self.locked);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rom_select_register = state.read_u8()?;
+        self.locked = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::tests::test_rom;
+
+    /// A 64 KiB Sachen ROM carrying Nintendo's logo only through the scrambled
+    /// header, plus the CGB copy with A7 set for the MMC2.
+    fn sachen_rom(mmc2: bool) -> Vec<u8> {
+        let mut rom = test_rom(0x00, 0x01, 0x00);
+        rom[NINTENDO_LOGO_ADDR].fill(0);
+        for (address, byte) in NINTENDO_LOGO_ADDR.zip(NINTENDO_LOGO) {
+            rom[sachen_scramble(address)] = byte;
+        }
+        if mmc2 {
+            rom[NINTENDO_LOGO_ADDR.start + 0x80..NINTENDO_LOGO_ADDR.end + 0x80].copy_from_slice(&NINTENDO_LOGO);
+        }
+        rom
+    }
+
+    #[test]
+    fn scramble_swaps_address_lines() {
+        assert_eq!(sachen_scramble(0x0001), 0x0040);
+        assert_eq!(sachen_scramble(0x0040), 0x0001);
+        assert_eq!(sachen_scramble(0x0002), 0x0010);
+        assert_eq!(sachen_scramble(0x0010), 0x0002);
+        assert_eq!(sachen_scramble(0x01AC), 0x01AC);
+        assert_eq!(sachen_scramble(0x0105), 0x0144);
+    }
+
+    #[test]
+    fn header_is_unscrambled_from_0x100() {
+        let mut rom = sachen_rom(false);
+        rom[0x41] = 0xAA;
+        let header = sachen_header(&rom);
+        assert_eq!(header.len(), HEADER_END);
+        assert_eq!(header[0x41], 0xAA);
+        assert_eq!(header[NINTENDO_LOGO_ADDR], NINTENDO_LOGO);
+        assert_ne!(rom[NINTENDO_LOGO_ADDR], NINTENDO_LOGO);
+    }
+
+    #[test]
+    fn detects_unlicensed_mappers() {
+        assert_eq!(detect_unlicensed(&test_rom(0x00, 0x01, 0x00)), None);
+        assert_eq!(detect_unlicensed(&sachen_rom(false)), Some(MapperKind::SachenMMC1));
+        assert_eq!(detect_unlicensed(&sachen_rom(true)), Some(MapperKind::SachenMMC2));
+
+        let mut wisdom_tree = test_rom(0x00, 0x01, 0x00);
+        wisdom_tree[0x150..0x15B].copy_from_slice(b"WISDOM TREE");
+        assert_eq!(detect_unlicensed(&wisdom_tree), Some(MapperKind::WisdomTree));
+        // A 32 KiB ROM has nothing to bank.
+        wisdom_tree.truncate(0x8000);
+        assert_eq!(detect_unlicensed(&wisdom_tree), None);
+
+        let mut m161 = test_rom(0x10, 0x03, 0x00);
+        assert_eq!(detect_unlicensed(&m161), Some(MapperKind::M161));
+        m161[RAM_SIZE_ADDR] = 0x02;
+        assert_eq!(detect_unlicensed(&m161), None);
+        assert_eq!(detect_unlicensed(&m161[..0x100]), None);
+    }
+
+    #[test]
+    fn wisdom_tree_selects_banks_with_the_address() {
+        let mut mbc = WisdomTree::new(test_rom(0x00, 0x02, 0x00)).unwrap();
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [0, 1]);
+        mbc.write(0x0002, 0xFF);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [4, 5]);
+        mbc.write(0x3F01, 0x00);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [2, 3]);
+        // Bank numbers wrap around the four 32 KiB banks.
+        mbc.write(0x0007, 0x00);
+        assert_eq!(mbc.read(0x0200), 6);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0x0200), 6);
+    }
+
+    #[test]
+    fn sachen_outer_registers_lock_with_the_selected_bank() {
+        let mut mbc = Sachen::new(test_rom(0x00, 0x03, 0x00)).unwrap();
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [0, 1]);
+        mbc.write(0x0000, 0x08);
+        assert_eq!(mbc.read(0x0200), 0);
+
+        // Bits 4 and 5 of the selected bank unlock the base and mask.
+        mbc.write(0x2000, 0x30);
+        mbc.write(0x0000, 0x08);
+        mbc.write(0x4000, 0xF8);
+        mbc.write(0x2000, 0x03);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [8, 11]);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4200), 9);
+
+        mbc.write(0x0000, 0x00);
+        mbc.write(0x4000, 0x00);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [8, 9]);
+    }
+
+    #[test]
+    fn m161_bank_select_is_write_once() {
+        let mut mbc = M161::new(test_rom(0x10, 0x03, 0x00)).unwrap();
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [0, 1]);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.read(0x0200), 0);
+        mbc.write(0x4000, 0x0B);
+        assert_eq!([mbc.read(0x0200), mbc.read(0x4200)], [6, 7]);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0x0200), 6);
+    }
+}
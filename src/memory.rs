@@ -1,24 +1,38 @@
 #![allow(non_camel_case_types)]
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
+use crate::cartridge::{CartridgeHeader, LoadError};
 use crate::constants::*;
+use crate::frontend::ImageSource;
 use crate::interrupt::Interrupt;
-use crate::mappers::{Addressable, Cartridge, NoCartridge, RomOnly, MBC1, MBC3};
-
-impl std::convert::From<u8> for Cartridge {
-    fn from(value: u8) -> Self {
-        match value {
-            0x00 => Cartridge::RomOnly,
-            0x01 => Cartridge::MBC1,
-            0x03 => Cartridge::MBC1_RAM_BATTERY,
-            0x05 => Cartridge::MBC2,
-            0x11 => Cartridge::MBC3,
-            0x13 => Cartridge::MBC3_RAM_BATTERY,
-            0x19 => Cartridge::MBC5,
-            0x20 => Cartridge::MBC6,
-            _ => panic!("Unexpected value for Cartrigde '{value}'"),
-        }
-    }
+use crate::mappers::{detect_unlicensed, new_mapper, sachen_header, Addressable, Cartridge, MapperKind, NoCartridge};
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+/// Rewrites the cartridge type byte at `header_offset`, and the header checksum,
+/// so the header describes `mapper`. Of the type bytes for that mapper, the one
+/// sharing most extra hardware with the original is picked.
+fn force_cartridge_type(game_bytes: &mut [u8], header_offset: usize, mapper: MapperKind) -> Result<(), LoadError> {
+    CartridgeHeader::parse(&game_bytes[header_offset..])?;
+    let header = &mut game_bytes[header_offset..];
+    let original = Cartridge::try_from(header[CARTRIDGE_TYPE_ADDR]).unwrap_or(Cartridge::new(mapper));
+    let shared_hardware = |cartridge: &Cartridge| {
+        [
+            cartridge.ram == original.ram,
+            cartridge.battery == original.battery,
+            cartridge.timer == original.timer,
+            cartridge.rumble == original.rumble,
+            cartridge.sensor == original.sensor,
+        ].iter().filter(|shared| **shared).count()
+    };
+    let type_byte = (0..=0xFF)
+        .filter(|value| Cartridge::try_from(*value).is_ok_and(|cartridge| cartridge.mapper == mapper))
+        .max_by_key(|value| Cartridge::try_from(*value).map_or(0, |cartridge| shared_hardware(&cartridge)))
+        .ok_or(LoadError::UnsupportedMapper(Cartridge::new(mapper)))?;
+    header[CARTRIDGE_TYPE_ADDR] = type_byte;
+    header[HEADER_CHECKSUM_ADDR] = header[TITLE_ADDR.start..HEADER_CHECKSUM_ADDR].iter()
+        .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+    Ok(())
 }
 
 pub struct AddressSpace {
@@ -40,8 +54,11 @@ pub struct AddressSpace {
     clock: u64,
     mapper: Box<dyn Addressable>,
     ch1_period_written: bool,
-    save_ram: bool,
+    /// Mapper used for the next ROMs loaded in place of the header's.
+    mapper_override: Option<MapperKind>,
     game_title: String,
+    global_checksum: u16,
+    save_dir: PathBuf,
 }
 
 impl AddressSpace {
@@ -63,29 +80,56 @@ impl AddressSpace {
             internal_div: 0,
             past_tick_tima_enabled: false,
             clock: 0,
-            mapper: Box::new(NoCartridge::new(Vec::new())),
+            mapper: Box::new(NoCartridge {}),
             ch1_period_written: false,
-            save_ram: false,
+            mapper_override: None,
             game_title: String::new(),
+            global_checksum: 0,
+            save_dir: PathBuf::from("./saved_games"),
         }
     }
 
-    pub fn quit(&self) {
-        if !self.save_ram {
-            return
-        }
-        if self.mapper.cartridge_type() == Some(Cartridge::MBC1) || self.mapper.cartridge_type() == Some(Cartridge::MBC3) {
-            let dir = format!("./saved_games/{}", self.game_title);
-            let state = self.mapper.save_persistent_state();
-            let result = std::fs::create_dir_all(&dir);
-            let mut file = std::fs::OpenOptions::new()
-            .create(true) // To create a new file
-            .write(true)
-            // either use the ? operator or unwrap since it returns a Result
-            .open(format!("{dir}/SAVE.bin")).unwrap();
-
-            file.write_all(&state);
+    /// Directory holding the battery save and save states of the loaded game.
+    pub fn game_save_dir(&self) -> PathBuf {
+        self.save_dir.join(&self.game_title)
+    }
+
+    /// Sets the directory battery saves are read from and written to, one subdirectory per game title.
+    pub fn set_save_dir(&mut self, dir: &Path) {
+        self.save_dir = dir.to_path_buf();
+    }
+
+    /// Forces the mapper of ROMs loaded afterwards, `None` goes back to the
+    /// header and the unlicensed mapper heuristics.
+    pub fn set_mapper_override(&mut self, mapper: Option<MapperKind>) {
+        self.mapper_override = mapper;
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mapper.set_tilt(x, y);
+    }
+
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mapper.set_image_source(source);
+    }
+
+    /// Writes the battery save of the loaded game. It goes to a temporary file
+    /// renamed over SAVE.bin, so a failed write leaves the previous save intact.
+    pub fn quit(&self) -> std::io::Result<()> {
+        if !self.mapper.has_battery() {
+            return Ok(())
         }
+        let dir = self.game_save_dir();
+        std::fs::create_dir_all(&dir)?;
+        let temp_path = dir.join("SAVE.bin.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(&self.mapper.save_persistent_state())?;
+        file.sync_all()?;
+        std::fs::rename(temp_path, dir.join("SAVE.bin"))
     }
 
     pub fn request_interrupt(&mut self, interrupt: Interrupt) {
@@ -95,41 +139,109 @@ impl AddressSpace {
         self.write(IF_ADDR, interrupt_flags);
     }
 
-    pub fn load_rom(&mut self, game_bytes: Vec<u8>) -> Result<(), String> {
-        let mut title_bytes = vec![0u8; game_bytes[0x134..0x143].len()];
-        title_bytes.copy_from_slice(&game_bytes[0x134..0x143]);
-        let title = std::str::from_utf8(&title_bytes).unwrap();
-        let cartridge_type: Cartridge = Cartridge::from(game_bytes[0x147]);
-        match cartridge_type {
-            Cartridge::RomOnly => self.mapper = Box::new(RomOnly::new(game_bytes)),
-            Cartridge::MBC1 => self.mapper = Box::new(MBC1::new(game_bytes)),
-            Cartridge::MBC1_RAM_BATTERY => {
-                self.mapper = Box::new(MBC1::new(game_bytes));
-                self.save_ram = true;
+    /// Validates the header and installs the cartridge's mapper, returning the
+    /// problems that did not stop the ROM from loading. On error the previously
+    /// loaded cartridge is kept.
+    pub fn load_rom(&mut self, mut game_bytes: Vec<u8>) -> Result<Vec<LoadError>, LoadError> {
+        let mut warnings = Vec::new();
+        let header_offset = CartridgeHeader::locate(&game_bytes);
+        let unlicensed = match self.mapper_override {
+            Some(mapper) if mapper.is_unlicensed() => Some(mapper),
+            Some(mapper) => {
+                force_cartridge_type(&mut game_bytes, header_offset, mapper)?;
+                None
             },
-            Cartridge::MBC3 => self.mapper = Box::new(MBC3::new(game_bytes)),
-            Cartridge::MBC3_RAM_BATTERY => {
-                self.mapper = Box::new(MBC3::new(game_bytes));
-                self.save_ram = true;
+            None => detect_unlicensed(&game_bytes),
+        };
+        let header = CartridgeHeader::parse_rom(&game_bytes)?;
+        // Unlicensed headers often fail the checksum or name another mapper.
+        let cartridge_type = match unlicensed {
+            Some(mapper) => Cartridge::new(mapper),
+            None => {
+                // Homebrew and patched ROMs often leave the checksum stale, so
+                // unlike the boot ROM this only warns.
+                if let Err(e) = header.verify_header_checksum() {
+                    warnings.push(e);
+                }
+                header.cartridge()?
             },
-            _ => unimplemented!("No mapper implemented for cartridge type {cartridge_type:?}"),
         };
-        println!("Cartridge mapper '{cartridge_type:?}'");
+        // Save directories are named after the first 15 title bytes whatever
+        // the header layout, so that existing saves keep being found.
+        let title = match cartridge_type.mapper {
+            MapperKind::SachenMMC1 | MapperKind::SachenMMC2 => String::from_utf8_lossy(&sachen_header(&game_bytes)[TITLE_ADDR]).into_owned(),
+            _ => String::from_utf8_lossy(&game_bytes[header_offset..][TITLE_ADDR]).into_owned(),
+        };
+        let mapper = new_mapper(cartridge_type, game_bytes)?;
+        self.mapper = mapper;
+        println!("Cartridge mapper '{cartridge_type}'");
         println!("Title '{title}'");
         self.game_title = title.trim_end_matches(char::from(0)).to_string();
-
-
-        if self.mapper.cartridge_type() == Some(Cartridge::MBC1) || self.mapper.cartridge_type() == Some(Cartridge::MBC3) {
-            let dir = format!("./saved_games/{}", self.game_title);
-            let save_path = format!("{dir}/SAVE.bin");
-            let data = std::fs::read(save_path).unwrap_or(Vec::new());
-            if data.len() > 0 {
-                println!("Found save, loading");
-                self.mapper.load_persistent_state(data);
+        self.global_checksum = header.global_checksum;
+
+
+        if self.mapper.has_battery() {
+            match std::fs::read(self.game_save_dir().join("SAVE.bin")) {
+                Ok(data) if !data.is_empty() => {
+                    println!("Found save, loading");
+                    self.mapper.load_persistent_state(data);
+                },
+                Ok(_) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => warnings.push(LoadError::UnreadableSave(e)),
             }
         }
 
-        Ok(())
+        Ok(warnings)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(self.game_title.as_bytes());
+        state.write_u16(self.global_checksum);
+        state.write_slice(&self.vram);
+        state.write_slice(&self.internal_ram);
+        state.write_slice(&self.oam);
+        state.write_slice(&self.empty_io);
+        state.write_slice(&self.standard_io);
+        state.write_slice(&self.empty_io2);
+        state.write_slice(&self.hram);
+        state.write_slice(&self.interrupt_enable);
+        state.write_i32(self.dma_start_address);
+        state.write_u16(self.dma_clock_t);
+        state.write_u8(self.joypad_state);
+        state.write_bool(self.oam_writeable);
+        state.write_bool(self.vram_writeable);
+        state.write_u16(self.internal_div);
+        state.write_bool(self.past_tick_tima_enabled);
+        state.write_u64(self.clock);
+        state.write_bool(self.ch1_period_written);
+        self.mapper.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let title = state.read_bytes()?;
+        let global_checksum = state.read_u16()?;
+        if title != self.game_title.as_bytes() || global_checksum != self.global_checksum {
+            return Err(StateError::WrongGame);
+        }
+        state.read_slice(&mut self.vram)?;
+        state.read_slice(&mut self.internal_ram)?;
+        state.read_slice(&mut self.oam)?;
+        state.read_slice(&mut self.empty_io)?;
+        state.read_slice(&mut self.standard_io)?;
+        state.read_slice(&mut self.empty_io2)?;
+        state.read_slice(&mut self.hram)?;
+        state.read_slice(&mut self.interrupt_enable)?;
+        self.dma_start_address = state.read_i32()?;
+        self.dma_clock_t = state.read_u16()?;
+        self.joypad_state = state.read_u8()?;
+        self.oam_writeable = state.read_bool()?;
+        self.vram_writeable = state.read_bool()?;
+        self.internal_div = state.read_u16()?;
+        self.past_tick_tima_enabled = state.read_bool()?;
+        self.clock = state.read_u64()?;
+        self.ch1_period_written = state.read_bool()?;
+        self.mapper.load_state(state)
     }
 
     pub fn read(&self, index: u16) -> u8 {
@@ -411,10 +523,7 @@ impl AddressSpace {
             self.past_tick_tima_enabled = tima_enabled;
             self.clock += 1;
         }
-        match self.mapper.cartridge_type() {
-            Some(Cartridge::MBC3) => self.mapper.tick(nticks),
-            _ => (),
-        }
+        self.mapper.tick(nticks);
         
     }
 }
\ No newline at end of file
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+
+/// Save states taken while playing, kept so the game can be run backwards.
+///
+/// Only the newest snapshot is stored whole. Every older one is stored as the
+/// XOR against its successor with the zero runs squeezed out, which is small
+/// because consecutive snapshots differ in few bytes. Rewinding walks the chain
+/// back from the newest snapshot, and the oldest entries are dropped once the
+/// buffer grows past its memory budget.
+pub struct RewindBuffer {
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+    budget: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            newest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+            budget,
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.enforce_budget();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    /// Bytes currently held by the snapshots.
+    pub fn size(&self) -> usize {
+        self.deltas_size + self.newest.as_ref().map_or(0, |state| state.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.take() {
+            if previous.len() == state.len() {
+                let delta = compress_delta(&previous, &state);
+                self.deltas_size += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                self.clear();
+            }
+        }
+        self.newest = Some(state);
+        self.enforce_budget();
+    }
+
+    /// Removes and returns the newest snapshot.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.len();
+            let mut previous = newest.clone();
+            match decompress_delta(&delta, &mut previous) {
+                Some(()) => self.newest = Some(previous),
+                // A delta that does not fit leaves nothing older to trust.
+                None => self.clear(),
+            }
+        }
+        Some(newest)
+    }
+
+    fn enforce_budget(&mut self) {
+        while self.size() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => {
+                    self.newest = None;
+                    break;
+                },
+            }
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return None;
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Encodes `old ^ new` as pairs of (zero run length, literal length, literals).
+fn compress_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < old.len() {
+        let zeros_start = i;
+        while i < old.len() && old[i] == new[i] {
+            i += 1;
+        }
+        let literals_start = i;
+        while i < old.len() && old[i] != new[i] {
+            i += 1;
+        }
+        write_varint(&mut out, literals_start - zeros_start);
+        write_varint(&mut out, i - literals_start);
+        out.extend(old[literals_start..i].iter().zip(&new[literals_start..i]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+/// Applies a delta from `compress_delta` to `state` in place. Returns `None`
+/// if the delta is malformed or runs past `state`, which is then partly applied.
+fn decompress_delta(delta: &[u8], state: &mut [u8]) -> Option<()> {
+    let mut pos = 0;
+    let mut i: usize = 0;
+    while pos < delta.len() {
+        i = i.checked_add(read_varint(delta, &mut pos)?)?;
+        let literals = read_varint(delta, &mut pos)?;
+        let bytes = delta.get(pos..pos.checked_add(literals)?)?;
+        let target = state.get_mut(i..i.checked_add(literals)?)?;
+        for (value, byte) in target.iter_mut().zip(bytes) {
+            *value ^= byte;
+        }
+        pos += literals;
+        i += literals;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let delta = compress_delta(old, new);
+        let mut state = new.to_vec();
+        decompress_delta(&delta, &mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn identical_states_round_trip() {
+        let state = vec![7u8; 300];
+        assert_eq!(round_trip(&state, &state), state);
+    }
+
+    #[test]
+    fn fully_different_states_round_trip() {
+        let old: Vec<u8> = (0..=255).collect();
+        let new: Vec<u8> = old.iter().map(|byte| !byte).collect();
+        assert_eq!(round_trip(&old, &new), old);
+    }
+
+    #[test]
+    fn sparse_changes_round_trip() {
+        let old = vec![0u8; 0x5000];
+        let mut new = old.clone();
+        new[0] = 1;
+        new[0x7F] = 2;
+        new[0x4000] = 3;
+        new[0x4FFF] = 4;
+        assert_eq!(round_trip(&old, &new), old);
+    }
+
+    #[test]
+    fn varint_boundaries() {
+        for value in [0, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let expected_len = match value {
+                0..=0x7F => 1,
+                0x80..=0x3FFF => 2,
+                0x4000 => 3,
+                _ => out.len(),
+            };
+            assert_eq!(out.len(), expected_len, "length of {value:#x}");
+            let mut pos = 0;
+            assert_eq!(read_varint(&out, &mut pos), Some(value));
+            assert_eq!(pos, out.len());
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_rejected() {
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0x80, 0x80], &mut pos), None);
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0xFF; 16], &mut pos), None);
+    }
+
+    #[test]
+    fn malformed_delta_is_rejected() {
+        let mut state = vec![0u8; 4];
+        // Skips 2 bytes, then claims 5 literals with only one present.
+        assert_eq!(decompress_delta(&[2, 5, 1], &mut state), None);
+        // Literals past the end of the state.
+        assert_eq!(decompress_delta(&[3, 2, 1, 1], &mut state), None);
+    }
+
+    #[test]
+    fn pop_returns_newest_first() {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        for value in 0..4u8 {
+            buffer.push(vec![value; 64]);
+        }
+        assert_eq!(buffer.len(), 4);
+        for value in (0..4u8).rev() {
+            assert_eq!(buffer.pop(), Some(vec![value; 64]));
+        }
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn length_mismatch_restarts_history() {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        buffer.push(vec![1; 64]);
+        buffer.push(vec![2; 64]);
+        buffer.push(vec![3; 32]);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.pop(), Some(vec![3; 32]));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn budget_evicts_oldest_snapshots() {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        for value in 0..10u8 {
+            let mut state = vec![0u8; 64];
+            state[value as usize] = 0xFF;
+            buffer.push(state);
+        }
+        assert_eq!(buffer.len(), 10);
+        // Each delta is a run, two literals and the trailing run: 6 bytes.
+        buffer.set_budget(64 + 2 * 6);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.size(), 64 + 2 * 6);
+        for value in (7..10u8).rev() {
+            assert_eq!(buffer.pop().unwrap()[value as usize], 0xFF);
+        }
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn budget_smaller_than_a_snapshot_keeps_nothing() {
+        let mut buffer = RewindBuffer::new(16);
+        buffer.push(vec![0; 64]);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.size(), 0);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"RGBS";
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    WrongGame,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{e}"),
+            StateError::BadMagic => write!(f, "not a save state file"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {version}, expected {SAVE_STATE_VERSION}"),
+            StateError::WrongGame => write!(f, "save state belongs to a different game"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<std::io::Error> for StateError {
+    fn from(e: std::io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+/// Appends little-endian fields to a growing save state buffer.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: Vec::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a buffer whose length is known to the reader.
+    pub fn write_slice(&mut self, values: &[u8]) {
+        self.data.extend_from_slice(values);
+    }
+
+    /// Writes a buffer prefixed by its length.
+    pub fn write_bytes(&mut self, values: &[u8]) {
+        self.write_u32(values.len() as u32);
+        self.write_slice(values);
+    }
+}
+
+/// Reads back the fields written by `StateWriter`, in the same order.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            pos: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.pos < len {
+            return Err(StateError::Truncated);
+        }
+        let values = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(values)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut values = [0; N];
+        values.copy_from_slice(self.take(N)?);
+        Ok(values)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, StateError> {
+        Ok(i32::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_le_bytes(self.take_array()?))
+    }
+
+    /// Fills `values` with a buffer written by `write_slice`.
+    pub fn read_slice(&mut self, values: &mut [u8]) -> Result<(), StateError> {
+        values.copy_from_slice(self.take(values.len())?);
+        Ok(())
+    }
+
+    /// Reads a buffer written by `write_bytes`.
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Reads a buffer written by `write_bytes` that must be exactly as long as `values`.
+    pub fn read_bytes_into(&mut self, values: &mut [u8]) -> Result<(), StateError> {
+        let len = self.read_u32()? as usize;
+        if len != values.len() {
+            return Err(StateError::Invalid("buffer length"));
+        }
+        self.read_slice(values)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0xAB);
+        writer.write_bool(true);
+        writer.write_u16(0xBEEF);
+        writer.write_u32(0xDEADBEEF);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_i32(-42);
+        writer.write_f32(1.5);
+        writer.write_slice(&[1, 2, 3]);
+        writer.write_bytes(&[4, 5]);
+        writer.write_bytes(&[6, 7, 8]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8().unwrap(), 0xAB);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0xBEEF);
+        assert_eq!(reader.read_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(reader.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(reader.read_i32().unwrap(), -42);
+        assert_eq!(reader.read_f32().unwrap(), 1.5);
+        let mut slice = [0; 3];
+        reader.read_slice(&mut slice).unwrap();
+        assert_eq!(slice, [1, 2, 3]);
+        assert_eq!(reader.read_bytes().unwrap(), [4, 5]);
+        let mut bytes = [0; 3];
+        reader.read_bytes_into(&mut bytes).unwrap();
+        assert_eq!(bytes, [6, 7, 8]);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn fields_are_little_endian() {
+        let mut writer = StateWriter::new();
+        writer.write_u16(0x1234);
+        writer.write_bytes(&[9]);
+        assert_eq!(writer.into_bytes(), [0x34, 0x12, 1, 0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn short_reads_are_truncated() {
+        let mut reader = StateReader::new(&[1, 2, 3]);
+        assert!(matches!(reader.read_u32(), Err(StateError::Truncated)));
+        // A length prefix larger than the data must not be trusted.
+        let mut reader = StateReader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0]);
+        assert!(matches!(reader.read_bytes(), Err(StateError::Truncated)));
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let mut reader = StateReader::new(&[2]);
+        assert!(matches!(reader.read_bool(), Err(StateError::Invalid("boolean"))));
+        let mut reader = StateReader::new(&[2, 0, 0, 0, 1, 2]);
+        assert!(matches!(reader.read_bytes_into(&mut [0; 3]), Err(StateError::Invalid("buffer length"))));
+    }
+}
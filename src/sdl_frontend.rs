@@ -0,0 +1,168 @@
+extern crate sdl2;
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::EventPump;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, SyncSender};
+
+use crate::constants::*;
+use crate::frontend::{AudioSink, InputSource, VideoSink};
+use crate::joypad::{Button, Joypad};
+
+struct AudioPlayer {
+    in_samples: Receiver<Vec<f32>>,
+}
+
+impl AudioCallback for AudioPlayer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        match self.in_samples.recv_timeout(std::time::Duration::from_secs_f32(0.030)) {
+            Ok(buffer) => {
+                for (i, x) in out.iter_mut().enumerate() {
+                    *x = buffer.get(i).copied().unwrap_or(0.0);
+                }
+            },
+            Err(_) => {
+                for x in out.iter_mut() {
+                    *x = 0.0;
+                }
+            },
+        }
+    }
+}
+
+/// Buffers waiting for the audio device before new ones are dropped.
+const AUDIO_QUEUE_MAX_BUFFERS: usize = 4;
+
+/// Keyboard bindings for each joypad button, checked in order.
+const KEY_BINDINGS: [(Button, &[Keycode]); 8] = [
+    (Button::Right, &[Keycode::Right, Keycode::D]),
+    (Button::Left, &[Keycode::Left, Keycode::A]),
+    (Button::Up, &[Keycode::Up, Keycode::W]),
+    (Button::Down, &[Keycode::Down, Keycode::S]),
+    (Button::A, &[Keycode::Enter]),
+    (Button::B, &[Keycode::Backspace, Keycode::Q]),
+    (Button::Select, &[Keycode::E]),
+    (Button::Start, &[Keycode::Space]),
+];
+
+pub struct SdlVideo {
+    canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+}
+
+impl SdlVideo {
+    fn render(&mut self, img: &[u8]) -> Result<(), String> {
+        let mut texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).map_err(|e| e.to_string())?;
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    let offset = y * pitch + x * 3;
+                    let value = img[y * SCREEN_WIDTH + x];
+                    buffer[offset] = value;
+                    buffer[offset + 1] = value;
+                    buffer[offset + 2] = value;
+                }
+            }
+        })?;
+
+        self.canvas.clear();
+        self.canvas.copy(&texture, None, Some(sdl2::rect::Rect::new(0, 0, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)))?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl VideoSink for SdlVideo {
+    fn present(&mut self, frame: &[u8]) {
+        if let Err(e) = self.render(frame) {
+            eprintln!("Failed to render frame: {e}");
+        }
+    }
+}
+
+pub struct SdlAudio {
+    _device: AudioDevice<AudioPlayer>,
+    out_samples: SyncSender<Vec<f32>>,
+}
+
+impl AudioSink for SdlAudio {
+    /// Drops the buffer when the device is too far behind, so a game running
+    /// faster than the device plays cannot build up unbounded latency.
+    fn queue(&mut self, samples: &[f32]) {
+        let _ = self.out_samples.try_send(samples.to_vec());
+    }
+}
+
+pub struct SdlInput {
+    event_pump: EventPump,
+    device_state: DeviceState,
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, joypad: &mut Joypad) -> bool {
+        let mut quit = false;
+        for event in self.event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                quit = true;
+            }
+        }
+        let keys: Vec<Keycode> = self.device_state.get_keys();
+        for (button, bound_keys) in KEY_BINDINGS {
+            joypad.set_button(button, bound_keys.iter().any(|key| keys.contains(key)));
+        }
+        quit || keys.contains(&Keycode::Escape)
+    }
+}
+
+/// Opens the SDL window and audio device and returns the sinks and input source built on them.
+pub fn init(window_scale: f32) -> Result<(SdlVideo, SdlAudio, SdlInput), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let audio_subsystem = sdl_context.audio()?;
+    let event_pump = sdl_context.event_pump()?;
+
+    let window = video_subsystem
+        .window("rust-sdl2 demo: Video", (SCREEN_WIDTH as f32 * window_scale) as u32, (SCREEN_HEIGHT as f32 * window_scale) as u32)
+        .position_centered()
+        .opengl()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    canvas.set_scale(window_scale, window_scale)?;
+    let texture_creator = canvas.texture_creator();
+
+    let desired_spec = AudioSpecDesired {
+        freq: Some(TARGET_SAMPLE_RATE as i32),
+        channels: Some(2),  // stereo
+        samples: Some(AUDIO_BUFFER_NUM_SAMPLES as u16),
+    };
+    let (tx, rx) = mpsc::sync_channel(AUDIO_QUEUE_MAX_BUFFERS);
+    let device = audio_subsystem.open_playback(None, &desired_spec, |_spec| {
+        AudioPlayer {
+            in_samples: rx,
+        }
+    })?;
+    device.resume();
+
+    Ok((
+        SdlVideo {
+            canvas,
+            texture_creator,
+        },
+        SdlAudio {
+            _device: device,
+            out_samples: tx,
+        },
+        SdlInput {
+            event_pump,
+            device_state: DeviceState::new(),
+        },
+    ))
+}
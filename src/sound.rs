@@ -1,17 +1,6 @@
-extern crate sdl2;
-
-use sdl2::libc::sleep;
-use sdl2::AudioSubsystem;
-use sdl2::audio::{AudioCallback, AudioDevice, AudioQueue, AudioSpecDesired, AudioStatus};
-use std::sync::mpsc;
-use std::sync::mpsc::{Sender, Receiver, SyncSender};
-
-
-use std::fs::{self, OpenOptions, File};
-use std::io::prelude::*;
-use std::time::Duration;
-
 use crate::constants::*;
+use crate::frontend::AudioSink;
+use crate::savestate::{StateError, StateReader, StateWriter};
 use crate::memory::AddressSpace;
 
 #[derive(PartialEq)]
@@ -45,32 +34,6 @@ enum LFSRWidth {
     Bits7,
 }
 
-struct AudioPlayer {
-    in_samples: Receiver<[f32; 2*AUDIO_BUFFER_NUM_SAMPLES]>,
-    capacity: f32,
-}
-
-impl AudioCallback for AudioPlayer {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        match self.in_samples.recv_timeout(std::time::Duration::from_secs_f32(0.030)) {
-            Ok(buffer) => {
-                for (i, x) in out.iter_mut().enumerate() {
-                    *x = buffer[i];
-                }
-            },
-            Err(s) => {
-                // println!("Failed to receive");
-                for (i, x) in out.iter_mut().enumerate() {
-                    *x = 0.0;
-                }
-            },
-        }
-    }
-}
-
-
 pub struct Channel {
     on: bool,
     volume: u8,
@@ -117,6 +80,52 @@ impl Channel {
         }
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.on);
+        state.write_u8(self.volume);
+        state.write_u8(self.env_sweep_step);
+        state.write_u8(self.env_sweep_pace);
+        state.write_bool(self.freq_sweep_enabled);
+        state.write_u8(self.length_i);
+        state.write_bool(self.length_enabled);
+        state.write_u8(self.sample_index);
+        state.write_u16(self.period_step);
+        state.write_u16(self.period);
+        state.write_u8(self.freq_sweep_i);
+        state.write_u8(self.freq_sweep_pace);
+        state.write_u8(self.initial_volume);
+        state.write_u16(self.shadow_period);
+        state.write_f32(self.capacity);
+        state.write_bool(self.negative_sweep_calc_executed);
+        state.write_u16(self.lfsr);
+        state.write_u8(self.past_sample);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.on = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.env_sweep_step = state.read_u8()?;
+        self.env_sweep_pace = state.read_u8()?;
+        self.freq_sweep_enabled = state.read_bool()?;
+        self.length_i = state.read_u8()?;
+        self.length_enabled = state.read_bool()?;
+        self.sample_index = state.read_u8()?;
+        self.period_step = state.read_u16()?;
+        self.period = state.read_u16()?;
+        self.freq_sweep_i = state.read_u8()?;
+        self.freq_sweep_pace = state.read_u8()?;
+        self.initial_volume = state.read_u8()?;
+        self.shadow_period = state.read_u16()?;
+        self.capacity = state.read_f32()?;
+        self.negative_sweep_calc_executed = state.read_bool()?;
+        self.lfsr = state.read_u16()?;
+        self.past_sample = state.read_u8()?;
+        if self.sample_index >= 32 {
+            return Err(StateError::Invalid("APU channel sample index"));
+        }
+        Ok(())
+    }
+
     pub fn buffer_to_analog(&mut self, value: u8) -> f32 {
         if value == 255 {
             let v = 0.0;
@@ -132,19 +141,17 @@ impl Channel {
     }
 }
 
+pub type AudioBuffer = [f32; 2 * AUDIO_BUFFER_NUM_SAMPLES];
+
 pub struct APU {
-    audio_subsystem: AudioSubsystem,
     div: Option<u8>,
     div_apu: u64,
-    device: AudioDevice<AudioPlayer>,
     ch1: Channel,
     ch2: Channel,
     ch3: Channel,
     ch4: Channel,
     clock: u64,
-    out_samples: Sender<[f32; 2*AUDIO_BUFFER_NUM_SAMPLES]>,
-    // out_samples: SyncSender<[u8; AUDIO_BUFFER_NUM_SAMPLES]>,
-    buffer: [f32; 2 * AUDIO_BUFFER_NUM_SAMPLES],
+    buffer: AudioBuffer,
     buffer_i: usize,
     frame_sequencer_i: u8,
     start_time: std::time::Instant,
@@ -153,39 +160,20 @@ pub struct APU {
     last_ch3_sample: u8,
     last_ch4_sample: u8,
     resample_frac: f32,
+    sample_step: f32,
 }
 
 
 impl APU {
-    pub fn new(audio_subsystem: AudioSubsystem) -> APU {
-        let desired_spec = AudioSpecDesired {
-            freq: Some(TARGET_SAMPLE_RATE as i32),
-            channels: Some(2),  // stereo
-            samples: Some(AUDIO_BUFFER_NUM_SAMPLES as u16),       // default sample size
-        };
-
-        let (tx, rx): (Sender<[f32; 2*AUDIO_BUFFER_NUM_SAMPLES]>, Receiver<[f32; 2*AUDIO_BUFFER_NUM_SAMPLES]>) = mpsc::channel();
-        
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            AudioPlayer {
-                in_samples: rx,
-                capacity: 0.0,
-            }
-        }).unwrap();
-
-        device.resume();
-
+    pub fn new() -> APU {
         APU {
-            audio_subsystem,
             div: None,
             div_apu: 0,
-            device,
             ch1: Channel::new(),
             ch2: Channel::new(),
             ch3: Channel::new(),
             ch4: Channel::new(),
             clock: 0,
-            out_samples: tx,
             buffer: [0f32; 2 * AUDIO_BUFFER_NUM_SAMPLES],
             buffer_i: 0,
             frame_sequencer_i: 0,
@@ -195,9 +183,61 @@ impl APU {
             last_ch3_sample: 255,
             last_ch4_sample: 255,
             resample_frac: 0.0,
+            sample_step: TARGET_SAMPLE_RATE as f32 / CLOCK_FREQ_HZ as f32,
         }
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.div.is_some());
+        state.write_u8(self.div.unwrap_or(0));
+        state.write_u64(self.div_apu);
+        self.ch1.save_state(state);
+        self.ch2.save_state(state);
+        self.ch3.save_state(state);
+        self.ch4.save_state(state);
+        state.write_u64(self.clock);
+        for sample in self.buffer.iter() {
+            state.write_f32(*sample);
+        }
+        state.write_u32(self.buffer_i as u32);
+        state.write_u8(self.frame_sequencer_i);
+        state.write_slice(&[self.last_ch1_sample, self.last_ch2_sample, self.last_ch3_sample, self.last_ch4_sample]);
+        state.write_f32(self.resample_frac);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let has_div = state.read_bool()?;
+        let div = state.read_u8()?;
+        self.div = if has_div { Some(div) } else { None };
+        self.div_apu = state.read_u64()?;
+        self.ch1.load_state(state)?;
+        self.ch2.load_state(state)?;
+        self.ch3.load_state(state)?;
+        self.ch4.load_state(state)?;
+        self.clock = state.read_u64()?;
+        for sample in self.buffer.iter_mut() {
+            *sample = state.read_f32()?;
+        }
+        self.buffer_i = state.read_u32()? as usize;
+        if self.buffer_i >= AUDIO_BUFFER_NUM_SAMPLES {
+            return Err(StateError::Invalid("APU buffer position"));
+        }
+        self.frame_sequencer_i = state.read_u8()?;
+        let mut last_samples = [0u8; 4];
+        state.read_slice(&mut last_samples)?;
+        [self.last_ch1_sample, self.last_ch2_sample, self.last_ch3_sample, self.last_ch4_sample] = last_samples;
+        self.resample_frac = state.read_f32()?;
+        Ok(())
+    }
+
+    /// Keeps the real-time sample rate at `TARGET_SAMPLE_RATE` while emulating at
+    /// `speed` times normal speed, by sampling proportionally less often. This
+    /// raises the pitch when running fast and lowers it in slow motion.
+    /// `speed` must be finite and positive.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.sample_step = TARGET_SAMPLE_RATE as f32 / CLOCK_FREQ_HZ as f32 / speed;
+    }
+
     fn ch1_calc_new_freq(&mut self, memory: &mut AddressSpace) -> u16 {
         let mut new_freq = self.ch1.shadow_period >> ch1_period_sweep_step(memory);
         if ch1_sweep_direction(memory) == SweepDirection::Dec {
@@ -726,15 +766,15 @@ impl APU {
         }
     }
 
-    fn output_samples_if_req(&mut self, memory: &mut AddressSpace) {
+    fn output_samples_if_req(&mut self, audio: &mut dyn AudioSink) {
         if self.buffer_i == AUDIO_BUFFER_NUM_SAMPLES {
             self.buffer_i = 0;
-            let outcome = self.out_samples.send(self.buffer.clone());
+            audio.queue(&self.buffer);
         }
     }
 
-    fn gather_samples(&mut self, memory: &mut AddressSpace) {
-        self.resample_frac += (TARGET_SAMPLE_RATE as f32 / 4194304.0).fract();
+    fn gather_samples(&mut self, memory: &mut AddressSpace, audio: &mut dyn AudioSink) {
+        self.resample_frac += self.sample_step;
 
         if self.resample_frac >= 1.0 {
             self.resample_frac -= 1.0;
@@ -745,11 +785,11 @@ impl APU {
 
             // self.debug_file.write_all(&self.buffer[2 * self.buffer_i].to_be_bytes());
 
-            self.output_samples_if_req(memory);
+            self.output_samples_if_req(audio);
         }
     }
 
-    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace) {
+    pub fn tick(&mut self, nticks: u8, memory: &mut AddressSpace, audio: &mut dyn AudioSink) {
         // let device_status = self.device.status();
         // if !apu_enabled(memory) {
         //     if device_status == AudioStatus::Playing {
@@ -779,7 +819,7 @@ impl APU {
 
             self.update_samples(memory);
 
-            self.gather_samples(memory);
+            self.gather_samples(memory, audio);
 
             // TODO: continue mixing samples
             self.clock += 1;
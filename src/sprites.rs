@@ -1,12 +1,7 @@
-extern crate sdl2;
-
-use sdl2::pixels::Color;
-
 use std::cmp::Ordering;
 
 use crate::memory::AddressSpace;
-
-use std::convert::Into;
+use crate::savestate::{StateError, StateReader, StateWriter};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum ColorId {
@@ -18,15 +13,15 @@ pub(crate) enum ColorId {
     Blank = 5,
 }
 
-impl Into<Color> for ColorId {
-    fn into(self) -> Color {
+impl ColorId {
+    pub(crate) fn shade(self) -> u8 {
         match self {
-            ColorId::Zero => Color::RGB(255, 255, 255),
-            ColorId::One => Color::RGB(170, 170, 170),
-            ColorId::Two => Color::RGB(85, 85, 85),
-            ColorId::Three => Color::RGB(0, 0, 0),
-            ColorId::Debug => Color::RGB(255, 0, 0),
-            ColorId::Blank => Color::RGB(255, 255, 255),
+            ColorId::Zero => 255,
+            ColorId::One => 170,
+            ColorId::Two => 85,
+            ColorId::Three => 0,
+            ColorId::Debug => 255,
+            ColorId::Blank => 255,
         }
     }
 }
@@ -71,6 +66,22 @@ impl SpriteData {
         }
     }
 
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.write_slice(&[self.y, self.x, self.raw_tile_index, self.attrs.to_byte(), self.oam_index]);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<SpriteData, StateError> {
+        let mut sprite_bytes = [0u8; 5];
+        state.read_slice(&mut sprite_bytes)?;
+        Ok(SpriteData {
+            y: sprite_bytes[0],
+            x: sprite_bytes[1],
+            raw_tile_index: sprite_bytes[2],
+            attrs: SpriteAttributes::new(sprite_bytes[3]),
+            oam_index: sprite_bytes[4],
+        })
+    }
+
     pub(crate) fn wins_prio(&self, other: &Self) -> bool {
         if self.x == other.x {
             return self.oam_index < other.oam_index;
@@ -103,4 +114,11 @@ impl SpriteAttributes {
             palette: if (attrs >> 4) & 1 == 1 { ColorPalette::OBP1 } else { ColorPalette::OBP0 },
         }
     }
+
+    pub(crate) fn to_byte(&self) -> u8 {
+        (self.priority as u8) << 7
+            | (self.y_flip as u8) << 6
+            | (self.x_flip as u8) << 5
+            | ((self.palette == ColorPalette::OBP1) as u8) << 4
+    }
 }